@@ -1,8 +1,17 @@
-use std::env;
+fn main() {
+    // set below when pipy is built with tcmalloc
+    println!("cargo::rustc-check-cfg=cfg(feature, values(\"use_tcmalloc\"))");
+    // without the `libpipy` feature only the pure Rust modules are built, nothing to link
+    #[cfg(feature = "libpipy")]
+    build_libpipy();
+}
 
-use cmake::Config;
+#[cfg(feature = "libpipy")]
+fn build_libpipy() {
+    use std::env;
+
+    use cmake::Config;
 
-fn main() {
     let profile = env::var("PROFILE").unwrap();
 
     let mut config = Config::new("libs/pipy");
@@ -57,6 +66,7 @@ fn main() {
 }
 
 // check if tcmalloc library exists in the system
+#[cfg(feature = "libpipy")]
 fn exist_tcmalloc() -> bool {
     // TODO: look for a way to check if tcmalloc exists or libpipy use tcmalloc
     false
@@ -1,34 +1,98 @@
-///! Pipy Repo RESTful API Client
-///! according to the API doc: https://flomesh.io/pipy/docs/en/operating/repo/3-api
-///! some details may be different, please refer to pipy code in `pipy/src/admin-service.cpp`
+//! Pipy Repo RESTful API Client
+//! according to the API doc: https://flomesh.io/pipy/docs/en/operating/repo/3-api
+//! some details may be different, please refer to pipy code in `pipy/src/admin-service.cpp`
+use std::{net::IpAddr, time::Duration};
+
 use api::ApiError;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Certificate, Identity, Url,
+};
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod export;
+pub mod layout;
+pub mod logs;
+pub mod metrics;
+mod path;
+pub mod status;
+mod sync;
+mod transaction;
+pub mod watch;
+
+pub use export::{CodebaseManifest, ExportTarget, MANIFEST_FILE};
+pub use path::{CodebaseName, RepoPath};
+pub use sync::{SyncOptions, SyncReport};
+pub use transaction::CodebaseTransaction;
 
+#[derive(Clone)]
 pub struct ApiClient {
-    host: String,
-    port: u16,
+    client: reqwest::Client,
+    base_url: Url,
+    // kept for websocket connections, which don't go through `client`
+    headers: HeaderMap,
+    tls_connector: Option<native_tls::TlsConnector>,
 }
 impl ApiClient {
+    /// create a client for the pipy admin service listening on `host:port`
+    ///
+    /// panics if `host` can't be used as a url host, use [`ApiClient::builder`] to handle the error
     pub fn new(host: &str, port: u16) -> Self {
-        ApiClient {
-            host: host.to_string(),
-            port,
-        }
+        ApiClient::builder()
+            .host(host)
+            .port(port)
+            .build()
+            .expect("failed to build pipy api client")
+    }
+    pub fn builder() -> ApiClientBuilder {
+        ApiClientBuilder::default()
+    }
+    /// base url of the admin service, e.g. `http://127.0.0.1:6060/`
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
     }
     pub async fn get_codebase_list(&self) -> Result<Vec<String>, ApiError> {
-        api::get_codebase_list(&self.host, self.port).await
+        api::get_codebase_list(&self.client, &self.base_url).await
     }
     pub async fn create_codebase(&self, codebase_name: &str) -> Result<(), ApiError> {
-        api::create_codebase(&self.host, self.port, codebase_name).await
+        api::create_codebase(&self.client, &self.base_url, codebase_name).await
+    }
+    /// create a codebase derived from `base_name`, files not in the new codebase are inherited from the base
+    pub async fn create_derived_codebase(
+        &self,
+        codebase_name: &str,
+        base_name: &str,
+    ) -> Result<(), ApiError> {
+        api::create_derived_codebase(&self.client, &self.base_url, codebase_name, base_name).await
     }
     pub async fn get_codebase(&self, codebase_name: &str) -> Result<api::Codebase, ApiError> {
-        api::get_codebase(&self.host, self.port, codebase_name).await
+        api::get_codebase(&self.client, &self.base_url, codebase_name).await
+    }
+    pub async fn get_instances(&self, codebase_name: &str) -> Result<Vec<api::Instance>, ApiError> {
+        api::get_instances(&self.client, &self.base_url, codebase_name).await
+    }
+    /// fetch the codebase once, see [`api::CodebaseSnapshot::check_file`]
+    pub async fn snapshot(&self, codebase_name: &str) -> Result<api::CodebaseSnapshot, ApiError> {
+        let codebase = self.get_codebase(codebase_name).await?;
+        Ok(api::CodebaseSnapshot {
+            name: codebase_name.to_string(),
+            codebase,
+            fetched_at: std::time::Instant::now(),
+        })
+    }
+    pub async fn get_derivation_tree(
+        &self,
+        codebase_name: &str,
+    ) -> Result<api::DerivationTree, ApiError> {
+        api::get_derivation_tree(&self.client, &self.base_url, codebase_name).await
     }
     pub async fn get_file(
         &self,
         codebase_name: &str,
         file_name: &str,
     ) -> Result<Vec<u8>, ApiError> {
-        api::get_file(&self.host, self.port, codebase_name, file_name).await
+        api::get_file(&self.client, &self.base_url, codebase_name, file_name).await
     }
     pub async fn update_file(
         &self,
@@ -36,216 +100,1009 @@ impl ApiClient {
         file_name: &str,
         data: Vec<u8>,
     ) -> Result<(), ApiError> {
-        api::update_file(&self.host, self.port, codebase_name, file_name, data).await
+        api::update_file(&self.client, &self.base_url, codebase_name, file_name, data).await
+    }
+    /// the file is listed in [`api::Codebase::erased_files`] until the changes are published
+    pub async fn delete_file(&self, codebase_name: &str, file_name: &str) -> Result<(), ApiError> {
+        api::delete_file(&self.client, &self.base_url, codebase_name, file_name).await
+    }
+    pub async fn delete_codebase(&self, codebase_name: &str) -> Result<(), ApiError> {
+        api::delete_codebase(&self.client, &self.base_url, codebase_name).await
+    }
+    /// change the entry script, base codebase or version, fails with [`ApiError::NotFound`]
+    /// if the new entry script is not a file of the codebase or is pending erase
+    pub async fn update_codebase(
+        &self,
+        codebase_name: &str,
+        update: &api::CodebaseUpdate,
+    ) -> Result<(), ApiError> {
+        api::update_codebase(&self.client, &self.base_url, codebase_name, update).await
+    }
+    /// publish the edited files, returns the new version
+    pub async fn publish_changes(
+        &self,
+        codebase_name: &str,
+    ) -> Result<api::CodebaseVersion, ApiError> {
+        api::publish_changes(&self.client, &self.base_url, codebase_name).await
+    }
+    /// publish only if nobody else published since `expected` was read
+    pub async fn publish_changes_if(
+        &self,
+        codebase_name: &str,
+        expected: &api::CodebaseVersion,
+    ) -> Result<api::CodebaseVersion, ApiError> {
+        api::publish_changes_if(&self.client, &self.base_url, codebase_name, expected).await
     }
-    pub async fn publish_changes(&self, codebase_name: &str) -> Result<(), ApiError> {
-        api::publish_changes(&self.host, self.port, codebase_name).await
+    pub async fn publish_version(
+        &self,
+        codebase_name: &str,
+        version: &api::CodebaseVersion,
+    ) -> Result<(), ApiError> {
+        api::publish_version(&self.client, &self.base_url, codebase_name, version).await
     }
 
-    /// TODO: how to use args to start the repo
+    /// start the codebase without args, see [`ApiClient::start_program`]
     pub async fn start_repo(&self, codebase_name: &str) -> Result<(), ApiError> {
-        api::start_repo(&self.host, self.port, codebase_name).await
+        self.start_program(codebase_name, &[]).await
     }
+    /// name of the running codebase
     pub async fn current_repo(&self) -> Result<Option<String>, ApiError> {
-        api::current_repo(&self.host, self.port).await
+        Ok(self
+            .current_program()
+            .await?
+            .map(|program| program.codebase().to_string()))
+    }
+    /// start the codebase, `args` are passed to the script as `pipy.argv.slice(1)`,
+    /// replacing the running program if any
+    pub async fn start_program(&self, codebase_name: &str, args: &[&str]) -> Result<(), ApiError> {
+        api::start_program(&self.client, &self.base_url, codebase_name, args).await
+    }
+    /// the running program with the args it was started with
+    pub async fn current_program(&self) -> Result<Option<api::Program>, ApiError> {
+        api::current_program(&self.client, &self.base_url).await
     }
     pub async fn stop_repo(&self) -> Result<(), ApiError> {
-        api::stop_repo(&self.host, self.port).await
+        api::stop_repo(&self.client, &self.base_url).await
+    }
+    /// runtime statistics of pipy, memory pools, objects, listeners and upstream connections
+    pub async fn get_status(&self) -> Result<status::PipyStatus, ApiError> {
+        status::get_status(&self.client, &self.base_url).await
+    }
+    /// all metrics of pipy, use [`metrics::MetricsSnapshot::delta`] with the last snapshot to get the changes
+    pub async fn get_metrics(&self) -> Result<metrics::MetricsSnapshot, ApiError> {
+        metrics::get_metrics(&self.client, &self.base_url).await
+    }
+}
+
+/// builder of [`ApiClient`], the default address is `http://127.0.0.1:6060/`
+pub struct ApiClientBuilder {
+    host: String,
+    port: u16,
+    https: bool,
+    base_url: Option<String>,
+    root_certificates: Vec<Vec<u8>>,
+    identity: Option<(Vec<u8>, Vec<u8>)>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    headers: Vec<(String, String)>,
+}
+impl Default for ApiClientBuilder {
+    fn default() -> Self {
+        ApiClientBuilder {
+            host: "127.0.0.1".to_string(),
+            port: 6060,
+            https: false,
+            base_url: None,
+            root_certificates: vec![],
+            identity: None,
+            timeout: None,
+            connect_timeout: None,
+            user_agent: None,
+            headers: vec![],
+        }
+    }
+}
+impl ApiClientBuilder {
+    /// host name or ip of the admin service, ipv6 literals can be given with or without brackets
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+    /// use `https` instead of `http`, for pipy started with `--admin-tls-cert`
+    pub fn https(mut self) -> Self {
+        self.https = true;
+        self
+    }
+    /// use a full base url such as `http://pipy.local:6060/`, overrides `host` and `port`
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+    /// timeout of a whole request, from connecting until the response body is read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+    /// trust the pem encoded CA certificate in addition to the system ones,
+    /// used to verify a self-signed admin service
+    pub fn root_certificate_pem(mut self, pem: &[u8]) -> Self {
+        self.root_certificates.push(pem.to_vec());
+        self
+    }
+    /// pem encoded client certificate and PKCS#8 private key,
+    /// required if pipy is started with `--admin-tls-trusted`
+    pub fn client_identity_pem(mut self, cert: &[u8], key: &[u8]) -> Self {
+        self.identity = Some((cert.to_vec(), key.to_vec()));
+        self
+    }
+    /// header sent with every request
+    pub fn default_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+    /// build a [`blocking::ApiClient`], which doesn't need an async runtime.
+    /// it gets its own connection pool, not shared with any async client
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(self) -> Result<blocking::ApiClient, ApiError> {
+        blocking::ApiClient::from_async(self.build()?)
+    }
+    pub fn build(self) -> Result<ApiClient, ApiError> {
+        let base_url = match self.base_url {
+            Some(base_url) => base_url,
+            None => {
+                let scheme = if self.https { "https" } else { "http" };
+                format!("{}://{}:{}/", scheme, format_host(&self.host), self.port)
+            }
+        };
+        let mut base_url = Url::parse(&base_url)?;
+        // `Url::join` replaces the last segment if the path doesn't end with '/'
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| ApiError::InvalidHeader(e.to_string()))?;
+            let value =
+                HeaderValue::from_str(value).map_err(|e| ApiError::InvalidHeader(e.to_string()))?;
+            headers.append(name, value);
+        }
+        let mut client = reqwest::Client::builder().default_headers(headers.clone());
+        if let Some(timeout) = self.timeout {
+            client = client.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            client = client.connect_timeout(timeout);
+        }
+        if let Some(user_agent) = self.user_agent {
+            client = client.user_agent(user_agent);
+        }
+        for pem in &self.root_certificates {
+            client = client.add_root_certificate(Certificate::from_pem(pem)?);
+        }
+        if let Some((cert, key)) = &self.identity {
+            client = client.identity(Identity::from_pkcs8_pem(cert, key)?);
+        }
+        let tls_connector = if base_url.scheme() == "https" {
+            let mut tls = native_tls::TlsConnector::builder();
+            for pem in &self.root_certificates {
+                tls.add_root_certificate(native_tls::Certificate::from_pem(pem)?);
+            }
+            if let Some((cert, key)) = &self.identity {
+                tls.identity(native_tls::Identity::from_pkcs8(cert, key)?);
+            }
+            Some(tls.build()?)
+        } else {
+            None
+        };
+        Ok(ApiClient {
+            client: client.build()?,
+            base_url,
+            headers,
+            tls_connector,
+        })
+    }
+}
+
+/// wrap ipv6 literals in brackets so they can be used in a url
+fn format_host(host: &str) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+        _ => host.to_string(),
     }
 }
 
 pub mod api {
-    use serde::{Deserialize, Serialize};
+    use std::{
+        collections::{BTreeMap, BTreeSet, VecDeque},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use reqwest::{Client, RequestBuilder, Response, StatusCode, Url};
+    use serde::{Deserialize, Deserializer, Serialize};
     use thiserror::Error;
 
+    use super::path::{trim_slash, CodebaseName, RepoPath};
+
     #[derive(Error, Debug)]
     pub enum ApiError {
+        #[error("{kind} {name} not found")]
+        NotFound { kind: ResourceKind, name: String },
+        /// 409, e.g. creating a codebase that already exists
+        #[error("conflict: {body}")]
+        Conflict { body: String },
+        /// 400, the body is pipy's error message
+        #[error("bad request: {body}")]
+        BadRequest { body: String },
+        /// 5xx
+        #[error("server error {status}: {body}")]
+        Server { status: StatusCode, body: String },
+        /// any other non-2xx status
+        #[error("unexpected status {status}: {body}")]
+        UnexpectedStatus { status: StatusCode, body: String },
+        /// failed to connect to the admin service
+        #[error("admin service unreachable: {0}")]
+        Unreachable(reqwest::Error),
+        #[error("request timeout: {0}")]
+        Timeout(reqwest::Error),
         #[error("reqwest error: {0}")]
-        ReqwestError(#[from] reqwest::Error),
+        ReqwestError(reqwest::Error),
+        #[error("websocket error: {0}")]
+        WebSocketError(Box<tokio_tungstenite::tungstenite::Error>),
+        #[error("tls error: {0}")]
+        TlsError(#[from] native_tls::Error),
         #[error("serde_json error: {0}")]
         SerdeJsonError(#[from] serde_json::Error),
-        #[error("error: {0} not found")]
-        NotFountError(String),
+        #[error("url error: {0}")]
+        UrlError(#[from] url::ParseError),
+        #[error("io error: {0}")]
+        IoError(#[from] std::io::Error),
+        #[error("invalid header: {0}")]
+        InvalidHeader(String),
+        #[error("invalid path: {0}")]
+        InvalidPath(String),
+        #[error("can't get the next version of {0:?}, publish an explicit version instead")]
+        InvalidVersion(String),
+        /// a [`super::CodebaseTransaction`] failed and some of its changes couldn't be undone
+        #[error("{error}, reverting {files:?} failed")]
+        RevertFailed {
+            error: Box<ApiError>,
+            files: Vec<String>,
+        },
+    }
+    impl ApiError {
+        pub fn is_not_found(&self) -> bool {
+            matches!(self, ApiError::NotFound { .. })
+        }
+        /// map a non-2xx response to an error, `kind` and `name` describe the requested resource
+        pub fn from_status(
+            status: StatusCode,
+            body: String,
+            kind: ResourceKind,
+            name: &str,
+        ) -> Self {
+            match status {
+                StatusCode::NOT_FOUND => ApiError::NotFound {
+                    kind,
+                    name: name.to_string(),
+                },
+                StatusCode::CONFLICT => ApiError::Conflict { body },
+                StatusCode::BAD_REQUEST => ApiError::BadRequest { body },
+                status if status.is_server_error() => ApiError::Server { status, body },
+                status => ApiError::UnexpectedStatus { status, body },
+            }
+        }
+    }
+    impl From<tokio_tungstenite::tungstenite::Error> for ApiError {
+        fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+            ApiError::WebSocketError(Box::new(e))
+        }
+    }
+    impl From<reqwest::Error> for ApiError {
+        fn from(e: reqwest::Error) -> Self {
+            if e.is_timeout() {
+                ApiError::Timeout(e)
+            } else if e.is_connect() {
+                ApiError::Unreachable(e)
+            } else {
+                ApiError::ReqwestError(e)
+            }
+        }
+    }
+
+    /// the kind of resource an api call works on, used in [`ApiError::NotFound`]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ResourceKind {
+        Codebase,
+        File,
+        Program,
+        Log,
+    }
+    impl std::fmt::Display for ResourceKind {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ResourceKind::Codebase => write!(f, "codebase"),
+                ResourceKind::File => write!(f, "file"),
+                ResourceKind::Program => write!(f, "program"),
+                ResourceKind::Log => write!(f, "log"),
+            }
+        }
     }
 
     #[derive(Serialize, Deserialize, Debug)]
     #[serde(rename_all = "camelCase")]
     pub struct Codebase {
-        pub version: String,
+        pub version: CodebaseVersion,
         pub path: String,
         pub main: String,            // entry script
         pub files: Vec<String>,      // file list
         pub edit_files: Vec<String>, // files that have been modified but not submitted
         pub erased_files: Vec<String>,
         pub base_files: Vec<String>,
-        pub derived: Vec<String>,
-        // pub instances: Vec<String>, // TODO: didn't know schema, ignore temporarily
+        pub derived: Vec<String>, // codebases derived from this one
+        #[serde(default)]
+        pub base: Option<String>, // the codebase this one is derived from
+        #[serde(deserialize_with = "deserialize_instances")]
+        pub instances: Vec<Instance>, // workers running this codebase
+    }
+    impl Codebase {
+        /// local files without the ones pending erase, names without the leading '/'
+        pub fn current_files(&self) -> Vec<&str> {
+            self.files
+                .iter()
+                .filter(|f| !self.erased_files.contains(f))
+                .map(|f| trim_slash(f))
+                .collect()
+        }
+        /// whether `file_name` is a file of the codebase, including inherited ones.
+        /// a local file pending erase doesn't count, pipy answers 404 for it
+        pub fn contains_file(&self, file_name: &str) -> bool {
+            let file_name = trim_slash(file_name);
+            self.current_files().contains(&file_name)
+                || self.base_files.iter().any(|f| trim_slash(f) == file_name)
+        }
+        /// instances that don't run the current version yet
+        pub fn outdated_instances(&self) -> Vec<&Instance> {
+            self.instances
+                .iter()
+                .filter(|i| i.version.as_ref() != Some(&self.version))
+                .collect()
+        }
+        /// where each file of a derived codebase comes from, sorted by file name
+        pub fn file_origins(&self) -> Vec<(String, FileOrigin)> {
+            let local: BTreeSet<&str> = self.files.iter().map(|f| trim_slash(f)).collect();
+            let inherited: BTreeSet<&str> = self.base_files.iter().map(|f| trim_slash(f)).collect();
+            let mut origins: Vec<(String, FileOrigin)> = local
+                .union(&inherited)
+                .map(|f| {
+                    let origin = match (local.contains(f), inherited.contains(f)) {
+                        (true, true) => FileOrigin::Overridden,
+                        (true, false) => FileOrigin::Local,
+                        _ => FileOrigin::Inherited,
+                    };
+                    (f.to_string(), origin)
+                })
+                .collect();
+            origins.sort();
+            origins
+        }
     }
 
-    /// GET /api/v1/repo
-    pub async fn get_codebase_list(host: &str, port: u16) -> Result<Vec<String>, ApiError> {
-        let url = format!("http://{}:{}/api/v1/repo", host, port);
-        let resp = reqwest::get(&url).await?;
-        tracing::debug!("get_codebase_list: {:?}", resp);
-        // split the response by '\n'
-        let test = resp.text().await?;
-        if test.is_empty() {
-            return Ok(vec![]);
-        } else {
-            let codebase_list = test
-                .split('\n')
-                .map(|s| s[1..].to_string()) // remove prefix '/'
+    /// a pipy worker that runs a codebase and reports its status to the repo
+    #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct Instance {
+        #[serde(alias = "uuid")]
+        pub id: String,
+        pub name: String,
+        #[serde(alias = "ip")]
+        pub address: Option<String>,
+        /// codebase version the instance is running
+        pub version: Option<CodebaseVersion>,
+        pub status: Option<String>,
+        /// milliseconds since unix epoch
+        #[serde(alias = "timestamp")]
+        pub last_heartbeat: Option<f64>,
+    }
+    impl Instance {
+        /// `None` if there is no heartbeat or it is out of range
+        pub fn last_heartbeat_time(&self) -> Option<SystemTime> {
+            let millis = self.last_heartbeat?;
+            let since_epoch = Duration::try_from_secs_f64(millis.max(0.0) / 1000.0).ok()?;
+            UNIX_EPOCH.checked_add(since_epoch)
+        }
+    }
+
+    /// pipy lists instances either as an array or as an object keyed by instance id
+    fn deserialize_instances<'de, D>(deserializer: D) -> Result<Vec<Instance>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Instances {
+            List(Vec<Instance>),
+            Map(BTreeMap<String, Instance>),
+        }
+        Ok(match Instances::deserialize(deserializer)? {
+            Instances::List(instances) => instances,
+            Instances::Map(instances) => instances
+                .into_iter()
+                .map(|(id, mut instance)| {
+                    if instance.id.is_empty() {
+                        instance.id = id;
+                    }
+                    instance
+                })
+                .collect(),
+        })
+    }
+
+    /// version of a codebase, pipy takes any string but versions are usually numbers like "1" or "0.1"
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+    #[serde(transparent)]
+    pub struct CodebaseVersion(String);
+    impl CodebaseVersion {
+        pub fn new(version: &str) -> Self {
+            CodebaseVersion(version.to_string())
+        }
+        pub fn as_str(&self) -> &str {
+            &self.0
+        }
+        /// the version to publish after this one, the last '.' separated number is incremented:
+        /// "" -> "1", "1" -> "2", "0.1" -> "0.2", `None` if the version doesn't end with a number
+        pub fn next(&self) -> Option<CodebaseVersion> {
+            if self.0.is_empty() {
+                return Some(CodebaseVersion::new("1"));
+            }
+            let (prefix, last) = match self.0.rsplit_once('.') {
+                Some((prefix, last)) => (Some(prefix), last),
+                None => (None, self.0.as_str()),
+            };
+            let next = last.parse::<u64>().ok()?.checked_add(1)?;
+            Some(match prefix {
+                Some(prefix) => CodebaseVersion(format!("{}.{}", prefix, next)),
+                None => CodebaseVersion(next.to_string()),
+            })
+        }
+    }
+    impl std::fmt::Display for CodebaseVersion {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+    impl From<&str> for CodebaseVersion {
+        fn from(version: &str) -> Self {
+            CodebaseVersion::new(version)
+        }
+    }
+
+    /// a running program, `argv[0]` is the codebase path like the script path of a local pipy,
+    /// so `pipy.argv.slice(1)` in the script are the args
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    pub struct Program {
+        pub path: String,
+        #[serde(default)]
+        pub argv: Vec<String>,
+    }
+    impl Program {
+        /// fails with [`ApiError::InvalidPath`] if `codebase_name` isn't a valid codebase name
+        pub fn new(codebase_name: &str, args: &[&str]) -> Result<Self, ApiError> {
+            let path = CodebaseName::new(codebase_name)?.to_path();
+            let argv = std::iter::once(path.clone())
+                .chain(args.iter().map(|a| a.to_string()))
                 .collect();
-            Ok(codebase_list)
+            Ok(Program { path, argv })
+        }
+        /// the codebase name without leading '/'
+        pub fn codebase(&self) -> &str {
+            trim_slash(&self.path)
+        }
+        /// args after the codebase path
+        pub fn args(&self) -> &[String] {
+            self.argv.get(1..).unwrap_or_default()
+        }
+        /// the body of GET /api/v1/program, a plain path or a json object with argv
+        pub(super) fn parse(data: &str) -> Result<Option<Program>, ApiError> {
+            let data = data.trim();
+            if data.is_empty() {
+                Ok(None)
+            } else if data.starts_with('{') {
+                Ok(Some(serde_json::from_str(data)?))
+            } else {
+                Ok(Some(Program::new(data, &[])?))
+            }
+        }
+    }
+
+    /// a codebase fetched once, to validate file names locally instead of asking the server each time
+    #[derive(Debug)]
+    pub struct CodebaseSnapshot {
+        pub name: String,
+        pub codebase: Codebase,
+        pub fetched_at: Instant,
+    }
+    impl CodebaseSnapshot {
+        /// time since the snapshot was fetched, the codebase may have changed since then
+        pub fn age(&self) -> Duration {
+            self.fetched_at.elapsed()
+        }
+        /// fails with [`ApiError::NotFound`] if the snapshot doesn't contain `file_name`
+        pub fn check_file(&self, file_name: &str) -> Result<(), ApiError> {
+            if self.codebase.contains_file(file_name) {
+                Ok(())
+            } else {
+                Err(ApiError::NotFound {
+                    kind: ResourceKind::File,
+                    name: file_name.to_string(),
+                })
+            }
         }
     }
 
+    /// properties of a codebase to change, `None` fields are left untouched
+    #[derive(Serialize, Default, Debug, Clone)]
+    pub struct CodebaseUpdate {
+        /// entry script, must be one of the codebase files
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub main: Option<String>,
+        /// codebase to derive from
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub base: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub version: Option<CodebaseVersion>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum FileOrigin {
+        /// only exists in this codebase
+        Local,
+        /// comes from the base codebase unchanged
+        Inherited,
+        /// exists in the base codebase but is replaced by this codebase
+        Overridden,
+    }
+
+    /// a codebase and all codebases derived from it, recursively
+    #[derive(Debug)]
+    pub struct DerivationTree {
+        pub name: String,
+        pub codebase: Codebase,
+        pub derived: Vec<DerivationTree>,
+    }
+    impl DerivationTree {
+        /// depth-first walk of the tree, with the depth of each node, the root is 0
+        pub fn walk(&self) -> Vec<(usize, &DerivationTree)> {
+            let mut nodes = vec![];
+            let mut stack = vec![(0, self)];
+            while let Some((depth, node)) = stack.pop() {
+                nodes.push((depth, node));
+                stack.extend(node.derived.iter().rev().map(|child| (depth + 1, child)));
+            }
+            nodes
+        }
+    }
+
+    /// join `path` to the base url of the admin service
+    pub(super) fn endpoint(base_url: &Url, path: &str) -> Result<Url, ApiError> {
+        Ok(base_url.join(path)?)
+    }
+
+    /// api/v1/repo/[CODEBASE] with the name validated and percent-encoded
+    pub(super) fn repo_url(base_url: &Url, codebase_name: &str) -> Result<Url, ApiError> {
+        let name = CodebaseName::new(codebase_name)?;
+        endpoint(base_url, &format!("api/v1/repo/{}", name.encoded()))
+    }
+
+    /// api/v1/repo-files/[CODEBASE]/[FILE_NAME] with both validated and percent-encoded
+    pub(super) fn file_url(
+        base_url: &Url,
+        codebase_name: &str,
+        file_name: &str,
+    ) -> Result<Url, ApiError> {
+        let name = CodebaseName::new(codebase_name)?;
+        let file = RepoPath::new(file_name)?;
+        endpoint(
+            base_url,
+            &format!("api/v1/repo-files/{}/{}", name.encoded(), file.encoded()),
+        )
+    }
+
+    /// send the request, turn a non-2xx status into an error about the resource `kind` named `name`
+    pub(super) async fn send(
+        request: RequestBuilder,
+        op: &str,
+        kind: ResourceKind,
+        name: &str,
+    ) -> Result<Response, ApiError> {
+        let resp = request.send().await?;
+        tracing::debug!("{}: {:?}", op, resp);
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await?;
+            tracing::debug!("{} Error, body: {:?}", op, body);
+            return Err(ApiError::from_status(status, body, kind, name));
+        }
+        Ok(resp)
+    }
+
+    /// GET /api/v1/repo
+    pub async fn get_codebase_list(
+        client: &Client,
+        base_url: &Url,
+    ) -> Result<Vec<String>, ApiError> {
+        let url = endpoint(base_url, "api/v1/repo")?;
+        let resp = send(
+            client.get(url),
+            "get_codebase_list",
+            ResourceKind::Codebase,
+            "",
+        )
+        .await?;
+        // one codebase path per line, like `/team/gateway`
+        let text = resp.text().await?;
+        Ok(text
+            .lines()
+            .map(trim_slash)
+            .filter(|name| !name.is_empty())
+            .map(|name| name.to_string())
+            .collect())
+    }
+
     /// POST /api/v1/repo/[CODEBASE]
-    /// TODO: support create form a base codebase
     pub async fn create_codebase(
-        host: &str,
-        port: u16,
+        client: &Client,
+        base_url: &Url,
         codebase_name: &str,
     ) -> Result<(), ApiError> {
-        let url = format!("http://{}:{}/api/v1/repo/{}", host, port, codebase_name);
-        let resp = reqwest::Client::new().post(&url).send().await?;
-        tracing::debug!("create_codebase: {:?}", resp);
-        if let Err(e) = resp.error_for_status_ref() {
-            tracing::debug!("create_codebase Error, body: {:?}", resp.text().await?);
-            return Err(e.into());
-        }
+        let url = repo_url(base_url, codebase_name)?;
+        send(
+            client.post(url),
+            "create_codebase",
+            ResourceKind::Codebase,
+            codebase_name,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// POST /api/v1/repo/[CODEBASE]
+    /// $ curl -X POST http://localhost:6060/api/v1/repo/derived --data '{"base": "/base"}'
+    pub async fn create_derived_codebase(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+        base_name: &str,
+    ) -> Result<(), ApiError> {
+        let url = repo_url(base_url, codebase_name)?;
+        let body = serde_json::json!({ "base": CodebaseName::new(base_name)?.to_path() });
+        send(
+            client.post(url).body(body.to_string()),
+            "create_derived_codebase",
+            ResourceKind::Codebase,
+            codebase_name,
+        )
+        .await?;
         Ok(())
     }
 
     /// GET /api/v1/repo/[CODEBASE]
     pub async fn get_codebase(
-        host: &str,
-        port: u16,
+        client: &Client,
+        base_url: &Url,
         codebase_name: &str,
     ) -> Result<Codebase, ApiError> {
-        let code_base_list = get_codebase_list(host, port).await?;
-        if !code_base_list.contains(&codebase_name.to_string())
-            && !code_base_list.contains(&format!("/{}", codebase_name))
-        {
-            return Err(ApiError::NotFountError(codebase_name.to_string()));
-        }
-
-        let url = format!("http://{}:{}/api/v1/repo/{}", host, port, codebase_name);
-        let resp = reqwest::get(&url).await?;
-        tracing::debug!("get_codebase: {:?}", resp);
+        let url = repo_url(base_url, codebase_name)?;
+        let resp = send(
+            client.get(url),
+            "get_codebase",
+            ResourceKind::Codebase,
+            codebase_name,
+        )
+        .await?;
         let data = resp.bytes().await?;
         tracing::debug!("get_codebase data: {:?}", data);
         let codebase: Codebase = serde_json::from_slice(&data)?;
         Ok(codebase)
     }
 
+    /// instances running the codebase, part of GET /api/v1/repo/[CODEBASE]
+    pub async fn get_instances(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+    ) -> Result<Vec<Instance>, ApiError> {
+        Ok(get_codebase(client, base_url, codebase_name)
+            .await?
+            .instances)
+    }
+
+    /// fetch the codebase and every codebase derived from it
+    pub async fn get_derivation_tree(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+    ) -> Result<DerivationTree, ApiError> {
+        // fetch breadth-first, then link the codebases from the leaves up
+        let mut fetched: Vec<(String, Codebase)> = vec![];
+        let mut queue = VecDeque::from([trim_slash(codebase_name).to_string()]);
+        while let Some(name) = queue.pop_front() {
+            if fetched.iter().any(|(n, _)| *n == name) {
+                continue;
+            }
+            let codebase = get_codebase(client, base_url, &name).await?;
+            queue.extend(codebase.derived.iter().map(|d| trim_slash(d).to_string()));
+            fetched.push((name, codebase));
+        }
+
+        let mut nodes: Vec<Option<DerivationTree>> = vec![];
+        for (name, codebase) in fetched.into_iter().rev() {
+            let derived = codebase
+                .derived
+                .iter()
+                .filter_map(|d| {
+                    let d = trim_slash(d);
+                    let node = nodes
+                        .iter_mut()
+                        .find(|n| matches!(n, Some(n) if n.name == d))?;
+                    node.take()
+                })
+                .collect();
+            nodes.push(Some(DerivationTree {
+                name,
+                codebase,
+                derived,
+            }));
+        }
+        Ok(nodes
+            .pop()
+            .flatten()
+            .expect("root codebase is fetched first"))
+    }
+
     /// GET /api/v1/repo-files/[CODEBASE]/[FILE_NAME]
     pub async fn get_file(
-        host: &str,
-        port: u16,
+        client: &Client,
+        base_url: &Url,
         codebase_name: &str,
         file_name: &str,
     ) -> Result<Vec<u8>, ApiError> {
-        let codebase_info = get_codebase(host, port, codebase_name).await?;
-        if !codebase_info.files.contains(&file_name.to_string())
-            && !codebase_info.files.contains(&format!("/{}", file_name))
-        {
-            return Err(ApiError::NotFountError(file_name.to_string()));
-        }
-
-        let url = format!(
-            "http://{}:{}/api/v1/repo-files/{}/{}",
-            host, port, codebase_name, file_name
-        );
-        let resp = reqwest::get(&url).await?;
-        tracing::debug!("get_file: {:?}", resp);
+        let url = file_url(base_url, codebase_name, file_name)?;
+        let resp = send(client.get(url), "get_file", ResourceKind::File, file_name).await?;
         let data = resp.bytes().await?;
         Ok(data.to_vec())
     }
 
     /// POST /api/v1/repo-files/[CODEBASE]/[FILE_NAME]
+    /// a new file is created if it doesn't exist, so 404 means the codebase is missing
     pub async fn update_file(
-        host: &str,
-        port: u16,
+        client: &Client,
+        base_url: &Url,
         codebase_name: &str,
         file_name: &str,
         data: Vec<u8>,
     ) -> Result<(), ApiError> {
-        let _ = get_codebase(host, port, codebase_name).await?;
+        let url = file_url(base_url, codebase_name, file_name)?;
+        send(
+            client.post(url).body(data),
+            "update_file",
+            ResourceKind::Codebase,
+            codebase_name,
+        )
+        .await?;
+        Ok(())
+    }
 
-        let url = format!(
-            "http://{}:{}/api/v1/repo-files/{}/{}",
-            host, port, codebase_name, file_name
-        );
-        let resp = reqwest::Client::new().post(&url).body(data).send().await?;
-        tracing::debug!("update_file: {:?}", resp);
-        if let Err(e) = resp.error_for_status_ref() {
-            tracing::debug!("update_file Error, body: {:?}", resp.text().await?);
-            return Err(e.into());
-        }
+    /// DELETE /api/v1/repo-files/[CODEBASE]/[FILE_NAME]
+    /// the file is kept in `files` and listed in `erased_files` until the next publish
+    pub async fn delete_file(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+        file_name: &str,
+    ) -> Result<(), ApiError> {
+        let url = file_url(base_url, codebase_name, file_name)?;
+        send(
+            client.delete(url),
+            "delete_file",
+            ResourceKind::File,
+            file_name,
+        )
+        .await?;
         Ok(())
     }
 
-    /// POST /api/v1/repo/[CODEBASE]
+    /// DELETE /api/v1/repo/[CODEBASE]
+    pub async fn delete_codebase(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+    ) -> Result<(), ApiError> {
+        let url = repo_url(base_url, codebase_name)?;
+        send(
+            client.delete(url),
+            "delete_codebase",
+            ResourceKind::Codebase,
+            codebase_name,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// PATCH /api/v1/repo/[CODEBASE]
+    /// $ curl -X PATCH http://localhost:6060/api/v1/repo/hello --data '{"main": "/app.js"}'
+    pub async fn update_codebase(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+        update: &CodebaseUpdate,
+    ) -> Result<(), ApiError> {
+        let mut update = update.clone();
+        if let Some(main) = &update.main {
+            let codebase_info = get_codebase(client, base_url, codebase_name).await?;
+            let main = RepoPath::new(main)?;
+            if !codebase_info.contains_file(main.as_str()) {
+                return Err(ApiError::NotFound {
+                    kind: ResourceKind::File,
+                    name: main.to_string(),
+                });
+            }
+            update.main = Some(main.to_path());
+        }
+        if let Some(base) = &update.base {
+            update.base = Some(CodebaseName::new(base)?.to_path());
+        }
+        patch_codebase(client, base_url, codebase_name, &update, "update_codebase").await
+    }
+
+    /// PATCH /api/v1/repo/[CODEBASE]
     /// $ curl -X PATCH http://localhost:6060/api/v1/repo/hello --data '{"version": '2'}'
+    /// publish with the next version of the current one, see [`CodebaseVersion::next`]
     pub async fn publish_changes(
-        host: &str,
-        port: u16,
+        client: &Client,
+        base_url: &Url,
         codebase_name: &str,
-    ) -> Result<(), ApiError> {
-        let codebase_info = get_codebase(host, port, codebase_name).await?;
-        let version_now: usize = codebase_info.version.parse().unwrap();
-        let url = format!("http://{}:{}/api/v1/repo/{}", host, port, codebase_name);
-        let body = format!(r#"{{"version": "{}"}}"#, version_now + 1);
-        let resp = reqwest::Client::new().patch(&url).body(body).send().await?;
-        tracing::debug!("publish_changes: {:?}", resp);
-        if let Err(e) = resp.error_for_status_ref() {
-            tracing::debug!("publish_changes Error, body: {:?}", resp.text().await?);
-            return Err(e.into());
+    ) -> Result<CodebaseVersion, ApiError> {
+        let codebase_info = get_codebase(client, base_url, codebase_name).await?;
+        publish_next(client, base_url, codebase_name, &codebase_info.version).await
+    }
+
+    /// publish only if the current version is `expected`, fails with [`ApiError::Conflict`] otherwise.
+    /// pipy has no conditional update, so a publish between the check and the update is still possible,
+    /// but edits based on a stale version are caught
+    pub async fn publish_changes_if(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+        expected: &CodebaseVersion,
+    ) -> Result<CodebaseVersion, ApiError> {
+        let codebase_info = get_codebase(client, base_url, codebase_name).await?;
+        if codebase_info.version != *expected {
+            return Err(ApiError::Conflict {
+                body: format!(
+                    "{} is at version {}, expected {}",
+                    codebase_name, codebase_info.version, expected
+                ),
+            });
         }
+        publish_next(client, base_url, codebase_name, &codebase_info.version).await
+    }
+
+    /// publish with an explicit version
+    pub async fn publish_version(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+        version: &CodebaseVersion,
+    ) -> Result<(), ApiError> {
+        let update = CodebaseUpdate {
+            version: Some(version.clone()),
+            ..Default::default()
+        };
+        patch_codebase(client, base_url, codebase_name, &update, "publish_version").await
+    }
+
+    async fn publish_next(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+        version_now: &CodebaseVersion,
+    ) -> Result<CodebaseVersion, ApiError> {
+        let version = version_now
+            .next()
+            .ok_or_else(|| ApiError::InvalidVersion(version_now.to_string()))?;
+        let update = CodebaseUpdate {
+            version: Some(version.clone()),
+            ..Default::default()
+        };
+        patch_codebase(client, base_url, codebase_name, &update, "publish_changes").await?;
+        Ok(version)
+    }
+
+    async fn patch_codebase(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+        update: &CodebaseUpdate,
+        op: &str,
+    ) -> Result<(), ApiError> {
+        let url = repo_url(base_url, codebase_name)?;
+        let body = serde_json::to_string(update)?;
+        send(
+            client.patch(url).body(body),
+            op,
+            ResourceKind::Codebase,
+            codebase_name,
+        )
+        .await?;
         Ok(())
     }
 
     /// POST /api/v1/program
     /// $ curl -X POST http://localhost:6060/api/v1/program --data '/repo_name'
-    pub async fn start_repo(host: &str, port: u16, codebase_name: &str) -> Result<(), ApiError> {
-        let _ = get_codebase(host, port, codebase_name).await?;
-
-        let url = format!("http://{}:{}/api/v1/program", host, port);
-        let body = format!(r#"/{}"#, codebase_name);
-        let resp = reqwest::Client::new().post(&url).body(body).send().await?;
-        tracing::debug!("start_repo: {:?}", resp);
-        if let Err(e) = resp.error_for_status_ref() {
-            tracing::debug!("start_repo Error, body: {:?}", resp.text().await?);
-            return Err(e.into());
-        }
+    /// $ curl -X POST http://localhost:6060/api/v1/program --data '{"path": "/repo_name", "argv": ["/repo_name", "--listen", "7777"]}'
+    /// the plain path is sent if there are no args
+    pub async fn start_program(
+        client: &Client,
+        base_url: &Url,
+        codebase_name: &str,
+        args: &[&str],
+    ) -> Result<(), ApiError> {
+        let url = endpoint(base_url, "api/v1/program")?;
+        let program = Program::new(codebase_name, args)?;
+        let body = if args.is_empty() {
+            program.path.clone()
+        } else {
+            serde_json::to_string(&program)?
+        };
+        send(
+            client.post(url).body(body),
+            "start_program",
+            ResourceKind::Codebase,
+            codebase_name,
+        )
+        .await?;
         Ok(())
     }
 
     /// GET /api/v1/program
     /// look up the running program
-    pub async fn current_repo(host: &str, port: u16) -> Result<Option<String>, ApiError> {
-        let url = format!("http://{}:{}/api/v1/program", host, port);
-        let resp = reqwest::get(&url).await?;
-        tracing::debug!("current_repo: {:?}", resp);
+    pub async fn current_program(
+        client: &Client,
+        base_url: &Url,
+    ) -> Result<Option<Program>, ApiError> {
+        let url = endpoint(base_url, "api/v1/program")?;
+        let resp = send(
+            client.get(url),
+            "current_program",
+            ResourceKind::Program,
+            "",
+        )
+        .await?;
         let data = resp.text().await?;
-        if data.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(data[1..].to_string())) // TODO: test if only have one running program
-        }
+        Program::parse(&data)
     }
 
     /// DELETE /api/v1/program
     /// stop the running program
-    pub async fn stop_repo(host: &str, port: u16) -> Result<(), ApiError> {
-        let url = format!("http://{}:{}/api/v1/program", host, port);
-        let resp = reqwest::Client::new().delete(&url).send().await?;
-        tracing::debug!("stop_repo: {:?}", resp);
-        if let Err(e) = resp.error_for_status_ref() {
-            tracing::debug!("stop_repo Error, body: {:?}", resp.text().await?);
-            return Err(e.into());
-        }
+    pub async fn stop_repo(client: &Client, base_url: &Url) -> Result<(), ApiError> {
+        let url = endpoint(base_url, "api/v1/program")?;
+        send(client.delete(url), "stop_repo", ResourceKind::Program, "").await?;
         Ok(())
     }
 }
@@ -253,9 +1110,21 @@ pub mod api {
 #[cfg(test)]
 mod tests {
 
-    use crate::{api_client::api::ApiError, start_pipy_repo, util::init_logger};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use reqwest::StatusCode;
+
+    use crate::api_client::api::{ApiError, ResourceKind};
+    #[cfg(feature = "libpipy")]
+    use crate::{start_pipy_repo, util::init_logger};
 
-    use super::{api::Codebase, ApiClient};
+    use super::{
+        api::{
+            Codebase, CodebaseUpdate, CodebaseVersion, DerivationTree, FileOrigin, Instance,
+            Program,
+        },
+        ApiClient,
+    };
 
     #[test]
     fn test_codebase_serde() {
@@ -265,9 +1134,9 @@ mod tests {
                     "path": "/test",
                     "main": "main.py",
                     "files": ["main.py", "util.py"],
-                    "edit_files": ["main.py"],
-                    "erased_files": [],
-                    "base_files": [],
+                    "editFiles": ["main.py"],
+                    "erasedFiles": [],
+                    "baseFiles": [],
                     "derived": [],
                     "instances": []
                 }"#;
@@ -279,9 +1148,9 @@ mod tests {
                     "path": "/test",
                     "main": "main.py",
                     "files": ["main.py", "util.py"],
-                    "edit_files": ["main.py"],
-                    "erased_files": [],
-                    "base_files": [],
+                    "editFiles": ["main.py"],
+                    "erasedFiles": [],
+                    "baseFiles": [],
                     "derived": [],
                     "instances": [],
                     "extra_field": "extra"
@@ -294,14 +1163,129 @@ mod tests {
                     "path": "/test",
                     "main": "main.py",
                     "files": ["main.py", "util.py"],
-                    "edit_files": ["main.py"],
-                    "erased_files": [],
-                    "base_files": [],
+                    "editFiles": ["main.py"],
+                    "erasedFiles": [],
+                    "baseFiles": [],
                     "derived": []
                 }"#;
         serde_json::from_str::<Codebase>(missing_field).expect_err("missing json failed");
     }
 
+    #[test]
+    fn test_instances_serde() {
+        let json = r#"
+                {
+                    "version": "2",
+                    "path": "/test",
+                    "main": "/main.js",
+                    "files": ["/main.js"],
+                    "editFiles": [],
+                    "erasedFiles": [],
+                    "baseFiles": [],
+                    "derived": [],
+                    "instances": {
+                        "8b2c": {
+                            "name": "worker-1",
+                            "ip": "10.0.0.1",
+                            "version": "2",
+                            "status": "running",
+                            "timestamp": 1718000000000
+                        },
+                        "9d4e": { "name": "worker-2", "version": "1" }
+                    }
+                }"#;
+        let codebase = serde_json::from_str::<Codebase>(json).unwrap();
+        assert_eq!(codebase.instances.len(), 2);
+        let worker = &codebase.instances[0];
+        assert_eq!(worker.id, "8b2c");
+        assert_eq!(worker.address.as_deref(), Some("10.0.0.1"));
+        assert_eq!(
+            worker.last_heartbeat_time(),
+            Some(UNIX_EPOCH + Duration::from_secs(1718000000))
+        );
+        let overflow: Instance =
+            serde_json::from_str(r#"{ "id": "x", "name": "x", "lastHeartbeat": 1e300 }"#).unwrap();
+        assert_eq!(overflow.last_heartbeat_time(), None);
+        let outdated: Vec<&str> = codebase
+            .outdated_instances()
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect();
+        assert_eq!(outdated, vec!["worker-2"]);
+
+        let list = r#"[{ "uuid": "8b2c", "name": "worker-1" }]"#;
+        let instances = serde_json::from_str::<Vec<Instance>>(list).unwrap();
+        assert_eq!(instances[0].id, "8b2c");
+    }
+
+    #[test]
+    fn test_base_url() {
+        let client = ApiClient::new("127.0.0.1", 6060);
+        assert_eq!(client.base_url().as_str(), "http://127.0.0.1:6060/");
+
+        let client = ApiClient::new("::1", 6060);
+        assert_eq!(client.base_url().as_str(), "http://[::1]:6060/");
+        let client = ApiClient::new("[::1]", 6060);
+        assert_eq!(client.base_url().as_str(), "http://[::1]:6060/");
+
+        let client = ApiClient::builder()
+            .base_url("http://pipy.local:8081/admin")
+            .build()
+            .unwrap();
+        assert_eq!(client.base_url().as_str(), "http://pipy.local:8081/admin/");
+
+        let err = ApiClient::builder()
+            .default_header("bad header", "value")
+            .build();
+        assert!(matches!(err, Err(ApiError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_error_from_status() {
+        let err = ApiError::from_status(
+            StatusCode::NOT_FOUND,
+            String::new(),
+            ResourceKind::File,
+            "main.js",
+        );
+        assert!(
+            matches!(&err, ApiError::NotFound { kind: ResourceKind::File, name } if name == "main.js")
+        );
+        assert_eq!(err.to_string(), "file main.js not found");
+        let err = ApiError::from_status(
+            StatusCode::CONFLICT,
+            "exists".to_string(),
+            ResourceKind::Codebase,
+            "hello",
+        );
+        assert!(matches!(err, ApiError::Conflict { body } if body == "exists"));
+        let err = ApiError::from_status(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "boom".to_string(),
+            ResourceKind::Codebase,
+            "hello",
+        );
+        assert!(matches!(err, ApiError::Server { body, .. } if body == "boom"));
+        let err = ApiError::from_status(
+            StatusCode::FORBIDDEN,
+            String::new(),
+            ResourceKind::Codebase,
+            "hello",
+        );
+        assert!(matches!(err, ApiError::UnexpectedStatus { status, .. } if status == 403));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable() {
+        // nothing listens on port 1
+        let client = ApiClient::new("127.0.0.1", 1);
+        assert!(matches!(
+            client.get_codebase_list().await,
+            Err(ApiError::Unreachable(_))
+        ));
+    }
+
+    #[cfg(feature = "libpipy")]
     #[tokio::test]
     async fn test_api() {
         init_logger("debug");
@@ -310,10 +1294,12 @@ mod tests {
 
         let repo_name = "hello";
         let client = ApiClient::new("127.0.0.1", pipy_port);
-        matches!(
-            client.get_codebase(repo_name).await.err().unwrap(),
-            ApiError::NotFountError(_)
-        );
+        assert!(client
+            .get_codebase(repo_name)
+            .await
+            .err()
+            .unwrap()
+            .is_not_found());
 
         // create codebase
         client.create_codebase(repo_name).await.unwrap();
@@ -360,10 +1346,486 @@ mod tests {
         assert_eq!(running_repo, another_repo_name);
         tracing::info!("running_repo change to: {:?}", running_repo);
 
+        let status = client.get_status().await.unwrap();
+        tracing::info!("status: {:?}", status);
+
         // stop the repo
         client.stop_repo().await.unwrap();
         let running_repo = client.current_repo().await.unwrap();
         assert!(running_repo.is_none(), "should not have running repo");
         tracing::info!("stop repo, test success");
     }
+
+    #[cfg(feature = "libpipy")]
+    #[tokio::test]
+    async fn test_delete() {
+        let pipy_port = 6062;
+        let _repo = start_pipy_repo(Some(pipy_port));
+        let client = ApiClient::new("127.0.0.1", pipy_port);
+
+        let repo_name = "to_delete";
+        client.create_codebase(repo_name).await.unwrap();
+        client
+            .update_file(repo_name, "util.js", b"export default {}".to_vec())
+            .await
+            .unwrap();
+        client.publish_changes(repo_name).await.unwrap();
+
+        // erased files stay pending until publish
+        client.delete_file(repo_name, "util.js").await.unwrap();
+        let codebase = client.get_codebase(repo_name).await.unwrap();
+        assert!(codebase.erased_files.iter().any(|f| f.ends_with("util.js")));
+        client.publish_changes(repo_name).await.unwrap();
+        let codebase = client.get_codebase(repo_name).await.unwrap();
+        assert!(codebase.erased_files.is_empty(), "publish erase failed");
+        assert!(!codebase.files.iter().any(|f| f.ends_with("util.js")));
+        assert!(matches!(
+            client.delete_file(repo_name, "util.js").await,
+            Err(ApiError::NotFound { .. })
+        ));
+
+        client.delete_codebase(repo_name).await.unwrap();
+        let codebase_list = client.get_codebase_list().await.unwrap();
+        assert!(!codebase_list.contains(&repo_name.to_string()));
+        assert!(matches!(
+            client.delete_codebase(repo_name).await,
+            Err(ApiError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_file_origins() {
+        let derived = r#"
+                {
+                    "version": "1",
+                    "path": "/tenant",
+                    "main": "/main.js",
+                    "files": ["/main.js", "/tenant.js"],
+                    "editFiles": [],
+                    "erasedFiles": [],
+                    "baseFiles": ["/main.js", "/util.js"],
+                    "derived": [],
+                    "base": "/gateway",
+                    "instances": {}
+                }"#;
+        let codebase = serde_json::from_str::<Codebase>(derived).unwrap();
+        assert_eq!(codebase.base.as_deref(), Some("/gateway"));
+        assert_eq!(
+            codebase.file_origins(),
+            vec![
+                ("main.js".to_string(), FileOrigin::Overridden),
+                ("tenant.js".to_string(), FileOrigin::Local),
+                ("util.js".to_string(), FileOrigin::Inherited),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_file() {
+        let codebase = r#"
+                {
+                    "version": "1",
+                    "path": "/tenant",
+                    "main": "/main.js",
+                    "files": ["/main.js", "/old.js", "/util.js"],
+                    "editFiles": [],
+                    "erasedFiles": ["/old.js", "/util.js"],
+                    "baseFiles": ["/util.js"],
+                    "derived": [],
+                    "base": "/gateway",
+                    "instances": {}
+                }"#;
+        let snapshot = super::api::CodebaseSnapshot {
+            name: "tenant".to_string(),
+            codebase: serde_json::from_str(codebase).unwrap(),
+            fetched_at: std::time::Instant::now(),
+        };
+        snapshot.check_file("/main.js").unwrap();
+        // erasing the local copy leaves the inherited one
+        snapshot.check_file("util.js").unwrap();
+        assert!(snapshot.check_file("old.js").unwrap_err().is_not_found());
+    }
+
+    #[test]
+    fn test_derivation_tree_walk() {
+        let codebase = |path: &str| Codebase {
+            version: "1".into(),
+            path: path.to_string(),
+            main: "/main.js".to_string(),
+            files: vec![],
+            edit_files: vec![],
+            erased_files: vec![],
+            base_files: vec![],
+            derived: vec![],
+            base: None,
+            instances: vec![],
+        };
+        let node = |name: &str, derived| DerivationTree {
+            name: name.to_string(),
+            codebase: codebase(name),
+            derived,
+        };
+        let tree = node(
+            "gateway",
+            vec![node("a", vec![node("a1", vec![])]), node("b", vec![])],
+        );
+        let walked: Vec<(usize, &str)> = tree
+            .walk()
+            .into_iter()
+            .map(|(depth, n)| (depth, n.name.as_str()))
+            .collect();
+        assert_eq!(walked, vec![(0, "gateway"), (1, "a"), (2, "a1"), (1, "b")]);
+    }
+
+    #[cfg(feature = "libpipy")]
+    #[tokio::test]
+    async fn test_derived_codebase() {
+        let pipy_port = 6063;
+        let _repo = start_pipy_repo(Some(pipy_port));
+        let client = ApiClient::new("127.0.0.1", pipy_port);
+
+        client.create_codebase("gateway").await.unwrap();
+        client
+            .update_file("gateway", "util.js", b"export default {}".to_vec())
+            .await
+            .unwrap();
+        client.publish_changes("gateway").await.unwrap();
+
+        client
+            .create_derived_codebase("tenant_a", "gateway")
+            .await
+            .unwrap();
+        client
+            .create_derived_codebase("tenant_b", "gateway")
+            .await
+            .unwrap();
+        let main_js = r#"pipy().listen(8081).serveHTTP(new Message('tenant a'))"#;
+        client
+            .update_file("tenant_a", "main.js", main_js.as_bytes().to_vec())
+            .await
+            .unwrap();
+        client.publish_changes("tenant_a").await.unwrap();
+
+        let tenant_a = client.get_codebase("tenant_a").await.unwrap();
+        let origins = tenant_a.file_origins();
+        assert!(origins.contains(&("util.js".to_string(), FileOrigin::Inherited)));
+        assert!(origins.contains(&("main.js".to_string(), FileOrigin::Overridden)));
+
+        let tree = client.get_derivation_tree("gateway").await.unwrap();
+        let mut derived: Vec<&str> = tree.derived.iter().map(|d| d.name.as_str()).collect();
+        derived.sort();
+        assert_eq!(derived, vec!["tenant_a", "tenant_b"]);
+        assert!(client
+            .create_derived_codebase("tenant_c", "missing")
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_codebase_version() {
+        let next = |v: &str| CodebaseVersion::new(v).next().map(|v| v.to_string());
+        assert_eq!(next("").as_deref(), Some("1"));
+        assert_eq!(next("1").as_deref(), Some("2"));
+        assert_eq!(next("0.1").as_deref(), Some("0.2"));
+        assert_eq!(next("1.9").as_deref(), Some("1.10"));
+        assert_eq!(next("v1-beta"), None);
+
+        let version = serde_json::from_str::<CodebaseVersion>(r#""0.1""#).unwrap();
+        assert_eq!(version.as_str(), "0.1");
+        assert_eq!(serde_json::to_string(&version).unwrap(), r#""0.1""#);
+    }
+
+    #[cfg(feature = "libpipy")]
+    #[tokio::test]
+    async fn test_publish_changes_if() {
+        let pipy_port = 6066;
+        let _repo = start_pipy_repo(Some(pipy_port));
+        let client = ApiClient::new("127.0.0.1", pipy_port);
+
+        let repo_name = "cas";
+        client.create_codebase(repo_name).await.unwrap();
+        let seen = client.get_codebase(repo_name).await.unwrap().version;
+
+        // another editor publishes first
+        client
+            .update_file(repo_name, "main.js", b"// other".to_vec())
+            .await
+            .unwrap();
+        let published = client.publish_changes(repo_name).await.unwrap();
+        assert_eq!(Some(published.clone()), seen.next());
+
+        client
+            .update_file(repo_name, "main.js", b"// mine".to_vec())
+            .await
+            .unwrap();
+        assert!(matches!(
+            client.publish_changes_if(repo_name, &seen).await,
+            Err(ApiError::Conflict { .. })
+        ));
+        let version = client
+            .publish_changes_if(repo_name, &published)
+            .await
+            .unwrap();
+        assert_eq!(Some(version), published.next());
+
+        client
+            .publish_version(repo_name, &"release-1".into())
+            .await
+            .unwrap();
+        let codebase = client.get_codebase(repo_name).await.unwrap();
+        assert_eq!(codebase.version.as_str(), "release-1");
+        assert!(matches!(
+            client.publish_changes(repo_name).await,
+            Err(ApiError::InvalidVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_program() {
+        let program = Program::new("ztm_agent", &["--listen", "127.0.0.1:7778"]).unwrap();
+        assert_eq!(
+            serde_json::to_string(&program).unwrap(),
+            r#"{"path":"/ztm_agent","argv":["/ztm_agent","--listen","127.0.0.1:7778"]}"#
+        );
+        assert_eq!(program.codebase(), "ztm_agent");
+        assert_eq!(program.args(), ["--listen", "127.0.0.1:7778"]);
+
+        let running = Program::parse("/hello").unwrap().unwrap();
+        assert_eq!(running.codebase(), "hello");
+        assert!(running.args().is_empty());
+        let running = Program::parse(&serde_json::to_string(&program).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(running, program);
+        assert_eq!(Program::parse("").unwrap(), None);
+        assert!(matches!(
+            Program::new("../x", &[]),
+            Err(ApiError::InvalidPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_codebase_update_serde() {
+        let update = CodebaseUpdate {
+            main: Some("/app.js".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&update).unwrap(),
+            r#"{"main":"/app.js"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&CodebaseUpdate::default()).unwrap(),
+            "{}"
+        );
+    }
+
+    #[cfg(feature = "libpipy")]
+    #[tokio::test]
+    async fn test_update_codebase() {
+        let pipy_port = 6064;
+        let _repo = start_pipy_repo(Some(pipy_port));
+        let client = ApiClient::new("127.0.0.1", pipy_port);
+
+        let repo_name = "entry";
+        client.create_codebase(repo_name).await.unwrap();
+        let app_js = r#"pipy().listen(8082).serveHTTP(new Message('app'))"#;
+        client
+            .update_file(repo_name, "app.js", app_js.as_bytes().to_vec())
+            .await
+            .unwrap();
+
+        // the entry script must exist
+        let missing = CodebaseUpdate {
+            main: Some("missing.js".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            client.update_codebase(repo_name, &missing).await,
+            Err(ApiError::NotFound { .. })
+        ));
+
+        let update = CodebaseUpdate {
+            main: Some("app.js".to_string()),
+            ..Default::default()
+        };
+        // nor be pending erase, it would be gone once published
+        client.delete_file(repo_name, "main.js").await.unwrap();
+        let erased = CodebaseUpdate {
+            main: Some("main.js".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            client.update_codebase(repo_name, &erased).await,
+            Err(ApiError::NotFound { .. })
+        ));
+
+        client.update_codebase(repo_name, &update).await.unwrap();
+        client.publish_changes(repo_name).await.unwrap();
+        let codebase = client.get_codebase(repo_name).await.unwrap();
+        assert_eq!(codebase.main, "/app.js");
+        assert_eq!(codebase.files, vec!["/app.js"]);
+
+        client.start_repo(repo_name).await.unwrap();
+        let resp = reqwest::get("http://127.0.0.1:8082")
+            .await
+            .expect("entry script not started")
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(resp, "app");
+        client.stop_repo().await.unwrap();
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_update_codebase_pending_erase() {
+        let server = crate::mock::MockRepoServer::start().await.unwrap();
+        let client = server.client();
+        client.create_codebase("entry").await.unwrap();
+        client
+            .update_file("entry", "app.js", b"app".to_vec())
+            .await
+            .unwrap();
+        client.publish_changes("entry").await.unwrap();
+
+        client.delete_file("entry", "app.js").await.unwrap();
+        let update = CodebaseUpdate {
+            main: Some("app.js".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            client.update_codebase("entry", &update).await,
+            Err(ApiError::NotFound { .. })
+        ));
+        client.publish_changes("entry").await.unwrap();
+        let codebase = client.get_codebase("entry").await.unwrap();
+        assert_eq!(codebase.main, "/main.js");
+        assert_eq!(codebase.files, vec!["/main.js"]);
+    }
+
+    #[cfg(feature = "libpipy")]
+    #[tokio::test]
+    async fn test_export_codebase() {
+        use super::{ExportTarget, MANIFEST_FILE};
+
+        let pipy_port = 6065;
+        let _repo = start_pipy_repo(Some(pipy_port));
+        let client = ApiClient::new("127.0.0.1", pipy_port);
+
+        let repo_name = "export";
+        client.create_codebase(repo_name).await.unwrap();
+        client
+            .update_file(repo_name, "lib/util.js", b"export default {}".to_vec())
+            .await
+            .unwrap();
+        client.publish_changes(repo_name).await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = client
+            .export_codebase(repo_name, ExportTarget::Directory(dir.path().to_path_buf()))
+            .await
+            .unwrap();
+        assert!(manifest.files.contains(&"lib/util.js".to_string()));
+        assert_eq!(
+            std::fs::read(dir.path().join("lib/util.js")).unwrap(),
+            b"export default {}"
+        );
+        assert!(dir.path().join(MANIFEST_FILE).exists());
+
+        let archive = tempfile::NamedTempFile::new().unwrap();
+        let writer = Box::new(archive.reopen().unwrap());
+        let tar_manifest = client
+            .export_codebase(repo_name, ExportTarget::TarGz(writer))
+            .await
+            .unwrap();
+        assert_eq!(tar_manifest, manifest);
+        assert!(archive.as_file().metadata().unwrap().len() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_request_count() {
+        use wiremock::{
+            matchers::{method, path, path_regex},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        let codebase = serde_json::json!({
+            "version": "1",
+            "path": "/ztm_agent",
+            "main": "/main.js",
+            "files": ["/main.js"],
+            "editFiles": [],
+            "erasedFiles": [],
+            "baseFiles": [],
+            "derived": [],
+            "instances": [],
+        });
+        Mock::given(method("POST"))
+            .and(path_regex("^/api/v1/repo-files/ztm_agent/.+$"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(5)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/repo-files/ztm_agent/main.js"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("main"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/repo-files/ztm_agent/missing.js"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/repo/ztm_agent"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(codebase))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/api/v1/repo/ztm_agent"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = ApiClient::builder()
+            .base_url(&server.uri())
+            .build()
+            .unwrap();
+        // one request per file, one GET and one PATCH to publish
+        for file in ["api.js", "db.js", "main.js", "mesh.js", "options.js"] {
+            client
+                .update_file("ztm_agent", file, file.as_bytes().to_vec())
+                .await
+                .unwrap();
+        }
+        client.publish_changes("ztm_agent").await.unwrap();
+        assert_eq!(
+            client.get_file("ztm_agent", "main.js").await.unwrap(),
+            b"main"
+        );
+        let err = client
+            .get_file("ztm_agent", "missing.js")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, ApiError::NotFound { kind: ResourceKind::File, name } if name == "missing.js")
+        );
+
+        // opt-in validation against a snapshot doesn't send requests per file
+        let snapshot = client.snapshot("ztm_agent").await.unwrap();
+        snapshot.check_file("main.js").unwrap();
+        assert!(snapshot
+            .check_file("missing.js")
+            .unwrap_err()
+            .is_not_found());
+
+        server.verify().await;
+        assert_eq!(server.received_requests().await.unwrap().len(), 10);
+    }
 }
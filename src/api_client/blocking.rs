@@ -0,0 +1,203 @@
+//! Blocking version of [`super::ApiClient`], enabled with the `blocking` feature
+//!
+//! every method runs the async one to completion on a runtime owned by the client,
+//! so both clients share the same requests and errors.
+//! like `reqwest::blocking`, the methods panic if called from inside an async runtime
+use std::{path::Path, pin::Pin, sync::Arc, time::Duration};
+
+use futures::{Stream, StreamExt};
+use tokio::runtime::Runtime;
+
+use super::{
+    api::{
+        ApiError, Codebase, CodebaseSnapshot, CodebaseUpdate, CodebaseVersion, DerivationTree,
+        Instance, Program,
+    },
+    layout::{CodebaseLayout, PipelineGraph},
+    logs::{LogLine, TailOptions},
+    metrics::MetricsSnapshot,
+    status::PipyStatus,
+    watch::CodebaseEvent,
+    ApiClientBuilder, CodebaseManifest, CodebaseTransaction, ExportTarget, SyncOptions, SyncReport,
+};
+
+#[derive(Clone)]
+pub struct ApiClient {
+    inner: super::ApiClient,
+    runtime: Arc<Runtime>,
+}
+
+/// forward to the async method of the same name, blocking until it completes
+macro_rules! blocking {
+    ($(
+        $(#[$meta:meta])*
+        fn $name:ident(&self $(, $arg:ident: $ty:ty)*) -> $ret:ty;
+    )*) => {$(
+        $(#[$meta])*
+        pub fn $name(&self $(, $arg: $ty)*) -> Result<$ret, ApiError> {
+            self.runtime.block_on(self.inner.$name($($arg),*))
+        }
+    )*};
+}
+
+impl ApiClient {
+    /// create a client for the pipy admin service listening on `host:port`
+    ///
+    /// panics if `host` can't be used as a url host, use [`ApiClientBuilder::build_blocking`] to handle the error
+    pub fn new(host: &str, port: u16) -> Self {
+        ApiClient::builder()
+            .host(host)
+            .port(port)
+            .build_blocking()
+            .expect("failed to build pipy api client")
+    }
+    pub fn builder() -> ApiClientBuilder {
+        ApiClientBuilder::default()
+    }
+    /// wrap an async client, requests share its connection pool.
+    ///
+    /// the pooled connections are driven by the runtime that opened them, so a client already used on
+    /// another runtime can fail with "dispatch task is gone" once that runtime is dropped.
+    /// pass a client that is only used through this wrapper, as [`ApiClientBuilder::build_blocking`] does
+    pub fn from_async(inner: super::ApiClient) -> Result<Self, ApiError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(ApiClient {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+    pub fn base_url(&self) -> &reqwest::Url {
+        self.inner.base_url()
+    }
+
+    blocking! {
+        fn get_codebase_list(&self) -> Vec<String>;
+        fn create_codebase(&self, codebase_name: &str) -> ();
+        fn create_derived_codebase(&self, codebase_name: &str, base_name: &str) -> ();
+        fn get_codebase(&self, codebase_name: &str) -> Codebase;
+        fn get_instances(&self, codebase_name: &str) -> Vec<Instance>;
+        fn snapshot(&self, codebase_name: &str) -> CodebaseSnapshot;
+        fn get_derivation_tree(&self, codebase_name: &str) -> DerivationTree;
+        fn get_file(&self, codebase_name: &str, file_name: &str) -> Vec<u8>;
+        fn update_file(&self, codebase_name: &str, file_name: &str, data: Vec<u8>) -> ();
+        fn delete_file(&self, codebase_name: &str, file_name: &str) -> ();
+        fn delete_codebase(&self, codebase_name: &str) -> ();
+        fn update_codebase(&self, codebase_name: &str, update: &CodebaseUpdate) -> ();
+        fn publish_changes(&self, codebase_name: &str) -> CodebaseVersion;
+        fn publish_changes_if(&self, codebase_name: &str, expected: &CodebaseVersion) -> CodebaseVersion;
+        fn publish_version(&self, codebase_name: &str, version: &CodebaseVersion) -> ();
+        fn start_repo(&self, codebase_name: &str) -> ();
+        fn current_repo(&self) -> Option<String>;
+        fn start_program(&self, codebase_name: &str, args: &[&str]) -> ();
+        fn current_program(&self) -> Option<Program>;
+        fn stop_repo(&self) -> ();
+        fn get_status(&self) -> PipyStatus;
+        fn get_metrics(&self) -> MetricsSnapshot;
+        fn sync_dir(&self, codebase_name: &str, path: &Path, options: &SyncOptions) -> SyncReport;
+        fn export_codebase(&self, codebase_name: &str, target: ExportTarget) -> CodebaseManifest;
+        fn list_logs(&self) -> Vec<String>;
+        fn get_log(&self, name: &str) -> Vec<String>;
+        fn get_pipeline_graph(&self, script: &str) -> PipelineGraph;
+        fn get_codebase_layout(&self, codebase_name: &str) -> CodebaseLayout;
+    }
+
+    /// follow a log with the default [`TailOptions`], the iterator never ends by itself
+    pub fn tail_log(&self, name: &str) -> BlockingStream<Result<LogLine, ApiError>> {
+        self.tail_log_with(name, TailOptions::default())
+    }
+    pub fn tail_log_with(
+        &self,
+        name: &str,
+        options: TailOptions,
+    ) -> BlockingStream<Result<LogLine, ApiError>> {
+        BlockingStream {
+            stream: Box::pin(self.inner.tail_log_with(name, options)),
+            runtime: self.runtime.clone(),
+        }
+    }
+    /// stage changes of the codebase, apply them with [`Self::commit_transaction`] or [`Self::apply_transaction`]
+    pub fn transaction(&self, codebase_name: &str) -> CodebaseTransaction {
+        self.inner.transaction(codebase_name)
+    }
+    pub fn commit_transaction(
+        &self,
+        transaction: CodebaseTransaction,
+    ) -> Result<CodebaseVersion, ApiError> {
+        self.runtime.block_on(transaction.commit())
+    }
+    pub fn apply_transaction(&self, transaction: CodebaseTransaction) -> Result<(), ApiError> {
+        self.runtime.block_on(transaction.apply())
+    }
+    /// poll the codebase for changes, see [`super::ApiClient::watch_codebase`]
+    pub fn watch_codebase(
+        &self,
+        codebase_name: &str,
+        interval: Duration,
+    ) -> BlockingStream<Result<CodebaseEvent, ApiError>> {
+        BlockingStream {
+            stream: Box::pin(self.inner.watch_codebase(codebase_name, interval)),
+            runtime: self.runtime.clone(),
+        }
+    }
+}
+
+/// an async stream of the client consumed as an iterator
+pub struct BlockingStream<T> {
+    stream: Pin<Box<dyn Stream<Item = T> + Send>>,
+    runtime: Arc<Runtime>,
+}
+impl<T> BlockingStream<T> {
+    /// wait for the next item at most `timeout`, `None` if it timed out or the stream ended
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<T> {
+        let next = self.stream.next();
+        self.runtime
+            .block_on(async { tokio::time::timeout(timeout, next).await })
+            .ok()
+            .flatten()
+    }
+}
+impl<T> Iterator for BlockingStream<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.runtime.block_on(self.stream.next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_blocking_client() {
+        // the mock server runs on its own runtime, the client must not be called inside one
+        let server_runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = server_runtime.block_on(async {
+            let server = MockServer::start().await;
+            Mock::given(method("GET"))
+                .and(path("/api/v1/repo"))
+                .respond_with(ResponseTemplate::new(200).set_body_string("/hello\n/world"))
+                .mount(&server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path("/api/v1/repo/missing"))
+                .respond_with(ResponseTemplate::new(404))
+                .mount(&server)
+                .await;
+            server
+        });
+
+        let client = ApiClient::builder()
+            .base_url(&server.uri())
+            .build_blocking()
+            .unwrap();
+        assert_eq!(client.get_codebase_list().unwrap(), vec!["hello", "world"]);
+        assert!(client.get_codebase("missing").unwrap_err().is_not_found());
+    }
+}
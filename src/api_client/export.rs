@@ -0,0 +1,200 @@
+//! Export a codebase to a local directory or a tar.gz archive
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    api::{ApiError, CodebaseVersion},
+    ApiClient, RepoPath,
+};
+
+/// name of the manifest written next to the exported files
+pub const MANIFEST_FILE: &str = ".codebase.json";
+
+/// where [`ApiClient::export_codebase`] writes the files
+pub enum ExportTarget {
+    /// write into a directory, created if it doesn't exist
+    Directory(PathBuf),
+    /// write a gzip compressed tar archive to the stream
+    TarGz(Box<dyn Write + Send>),
+}
+
+/// summary of an exported codebase, saved as [`MANIFEST_FILE`], `main` and `files` are relative paths
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CodebaseManifest {
+    pub name: String,
+    pub version: CodebaseVersion,
+    pub main: String,
+    pub files: Vec<String>,
+}
+
+impl ApiClient {
+    /// download the files of [`super::api::Codebase::current_files`], so neither files pending erase
+    /// nor files inherited from a base codebase are included
+    pub async fn export_codebase(
+        &self,
+        codebase_name: &str,
+        target: ExportTarget,
+    ) -> Result<CodebaseManifest, ApiError> {
+        let codebase = self.get_codebase(codebase_name).await?;
+        let mut files = vec![];
+        for file_name in codebase.current_files() {
+            let file_name = relative_path(file_name)?;
+            let data = self.get_file(codebase_name, &file_name).await?;
+            files.push((file_name, data));
+        }
+        let manifest = CodebaseManifest {
+            name: codebase_name.to_string(),
+            version: codebase.version,
+            main: relative_path(&codebase.main)?,
+            files: files.iter().map(|(name, _)| name.clone()).collect(),
+        };
+        tracing::debug!("export_codebase: {:?}", manifest);
+
+        match target {
+            ExportTarget::Directory(dir) => write_dir(&dir, &files, &manifest)?,
+            ExportTarget::TarGz(writer) => write_tar_gz(writer, &files, &manifest)?,
+        }
+        Ok(manifest)
+    }
+}
+
+/// turn a codebase file name into a relative path, rejecting names that escape the export root
+fn relative_path(file_name: &str) -> Result<String, ApiError> {
+    Ok(RepoPath::new(file_name)?.as_str().to_string())
+}
+
+fn write_dir(
+    dir: &Path,
+    files: &[(String, Vec<u8>)],
+    manifest: &CodebaseManifest,
+) -> Result<(), ApiError> {
+    for (file_name, data) in files {
+        let path = dir.join(file_name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, data)?;
+    }
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(
+        dir.join(MANIFEST_FILE),
+        serde_json::to_vec_pretty(manifest)?,
+    )?;
+    Ok(())
+}
+
+fn write_tar_gz(
+    writer: impl Write,
+    files: &[(String, Vec<u8>)],
+    manifest: &CodebaseManifest,
+) -> Result<(), ApiError> {
+    let mut builder = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+    let manifest = serde_json::to_vec_pretty(manifest)?;
+    let entries = files
+        .iter()
+        .map(|(name, data)| (name.as_str(), data.as_slice()))
+        .chain([(MANIFEST_FILE, manifest.as_slice())]);
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, name, data)?;
+    }
+    builder.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn sample() -> (Vec<(String, Vec<u8>)>, CodebaseManifest) {
+        let files = vec![
+            ("main.js".to_string(), b"main".to_vec()),
+            ("lib/util.js".to_string(), b"util".to_vec()),
+        ];
+        let manifest = CodebaseManifest {
+            name: "demo".to_string(),
+            version: "2".into(),
+            main: "main.js".to_string(),
+            files: files.iter().map(|(name, _)| name.clone()).collect(),
+        };
+        (files, manifest)
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(relative_path("/lib/util.js").unwrap(), "lib/util.js");
+        assert!(matches!(
+            relative_path("/../etc/passwd"),
+            Err(ApiError::InvalidPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_write_dir() {
+        let (files, manifest) = sample();
+        let dir = tempfile::tempdir().unwrap();
+        write_dir(dir.path(), &files, &manifest).unwrap();
+        assert_eq!(
+            std::fs::read(dir.path().join("lib/util.js")).unwrap(),
+            b"util"
+        );
+        let saved: CodebaseManifest =
+            serde_json::from_slice(&std::fs::read(dir.path().join(MANIFEST_FILE)).unwrap())
+                .unwrap();
+        assert_eq!(saved, manifest);
+    }
+
+    #[test]
+    fn test_write_tar_gz() {
+        let (files, manifest) = sample();
+        let mut archive = vec![];
+        write_tar_gz(&mut archive, &files, &manifest).unwrap();
+
+        let mut entries = vec![];
+        let mut tar = tar::Archive::new(GzDecoder::new(archive.as_slice()));
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().to_string();
+            let mut data = vec![];
+            entry.read_to_end(&mut data).unwrap();
+            entries.push((name, data));
+        }
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[..2], files[..]);
+        assert_eq!(entries[2].0, MANIFEST_FILE);
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_export_pending_erase() {
+        let server = crate::mock::MockRepoServer::start().await.unwrap();
+        let client = server.client();
+        client.create_codebase("hello").await.unwrap();
+        client
+            .update_file("hello", "util.js", b"util".to_vec())
+            .await
+            .unwrap();
+        client.publish_changes("hello").await.unwrap();
+        client.delete_file("hello", "util.js").await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = client
+            .export_codebase("hello", ExportTarget::Directory(dir.path().to_path_buf()))
+            .await
+            .unwrap();
+        assert_eq!(manifest.main, "main.js");
+        assert_eq!(manifest.files, vec!["main.js"]);
+        assert!(!dir.path().join("util.js").exists());
+    }
+}
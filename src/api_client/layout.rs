@@ -0,0 +1,363 @@
+//! Pipeline layout of a codebase, as drawn by the pipy GUI
+//! POST /api/v1/graph takes a script and returns its pipelines and the filters in each of them,
+//! filters such as `demuxHTTP` or `link` refer to the sub-pipelines they run
+use std::{collections::BTreeSet, fmt::Write};
+
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    api::{endpoint, send, ApiError, Codebase, ResourceKind},
+    ApiClient, RepoPath,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct PipelineGraph {
+    pub pipelines: Vec<Pipeline>,
+    /// script errors found while building the graph
+    pub errors: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Pipeline {
+    pub name: String,
+    /// e.g. `listen 8080` or `task`, empty for named sub-pipelines
+    pub label: String,
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Filter {
+    pub name: String,
+    pub label: String,
+    /// sub-pipelines started by the filter
+    pub links: Vec<PipelineRef>,
+}
+
+/// a pipeline referred by name, or by its index in [`PipelineGraph::pipelines`] for anonymous ones
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum PipelineRef {
+    Index(usize),
+    Name(String),
+}
+
+/// layout of every script file in a codebase
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CodebaseLayout {
+    pub modules: Vec<ModuleLayout>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModuleLayout {
+    pub file: String,
+    pub graph: PipelineGraph,
+}
+
+impl PipelineGraph {
+    fn resolve(&self, link: &PipelineRef) -> Option<usize> {
+        match link {
+            PipelineRef::Index(i) => (*i < self.pipelines.len()).then_some(*i),
+            PipelineRef::Name(name) => self.pipelines.iter().position(|p| p.name == *name),
+        }
+    }
+}
+
+impl CodebaseLayout {
+    /// render as Graphviz DOT, one cluster per module and per pipeline
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph pipy {\n  rankdir=LR;\n  node [shape=box];\n");
+        let mut edges = String::new();
+        for (m, module) in self.modules.iter().enumerate() {
+            let _ = writeln!(dot, "  subgraph cluster_m{} {{", m);
+            let _ = writeln!(dot, "    label=\"{}\";", escape(&module.file));
+            for (p, pipeline) in module.graph.pipelines.iter().enumerate() {
+                let _ = writeln!(dot, "    subgraph cluster_m{}_p{} {{", m, p);
+                let _ = writeln!(
+                    dot,
+                    "      label=\"{}\";",
+                    escape(&pipeline_title(pipeline))
+                );
+                if pipeline.filters.is_empty() {
+                    let _ = writeln!(dot, "      {} [label=\"(empty)\"];", node_id(m, p, 0));
+                }
+                for (f, filter) in pipeline.filters.iter().enumerate() {
+                    let _ = writeln!(
+                        dot,
+                        "      {} [label=\"{}\"];",
+                        node_id(m, p, f),
+                        escape(&filter_title(filter))
+                    );
+                    if f > 0 {
+                        let _ = writeln!(
+                            dot,
+                            "      {} -> {};",
+                            node_id(m, p, f - 1),
+                            node_id(m, p, f)
+                        );
+                    }
+                    for link in &filter.links {
+                        if let Some(target) = module.graph.resolve(link) {
+                            let _ = writeln!(
+                                edges,
+                                "  {} -> {} [style=dashed];",
+                                node_id(m, p, f),
+                                node_id(m, target, 0)
+                            );
+                        }
+                    }
+                }
+                dot.push_str("    }\n");
+            }
+            dot.push_str("  }\n");
+        }
+        dot.push_str(&edges);
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// render as a Mermaid flowchart
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart LR\n");
+        let mut edges = String::new();
+        for (m, module) in self.modules.iter().enumerate() {
+            let _ = writeln!(out, "  subgraph m{}[\"{}\"]", m, escape(&module.file));
+            for (p, pipeline) in module.graph.pipelines.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "    subgraph m{}_p{}[\"{}\"]",
+                    m,
+                    p,
+                    escape(&pipeline_title(pipeline))
+                );
+                if pipeline.filters.is_empty() {
+                    let _ = writeln!(out, "      {}[\"(empty)\"]", node_id(m, p, 0));
+                }
+                for (f, filter) in pipeline.filters.iter().enumerate() {
+                    let _ = writeln!(
+                        out,
+                        "      {}[\"{}\"]",
+                        node_id(m, p, f),
+                        escape(&filter_title(filter))
+                    );
+                    if f > 0 {
+                        let _ = writeln!(
+                            out,
+                            "      {} --> {}",
+                            node_id(m, p, f - 1),
+                            node_id(m, p, f)
+                        );
+                    }
+                    for link in &filter.links {
+                        if let Some(target) = module.graph.resolve(link) {
+                            let _ = writeln!(
+                                edges,
+                                "  {} -.-> {}",
+                                node_id(m, p, f),
+                                node_id(m, target, 0)
+                            );
+                        }
+                    }
+                }
+                out.push_str("    end\n");
+            }
+            out.push_str("  end\n");
+        }
+        out.push_str(&edges);
+        out
+    }
+}
+
+fn node_id(module: usize, pipeline: usize, filter: usize) -> String {
+    format!("m{}_p{}_f{}", module, pipeline, filter)
+}
+
+fn pipeline_title(pipeline: &Pipeline) -> String {
+    match (pipeline.name.is_empty(), pipeline.label.is_empty()) {
+        (false, false) => format!("{} ({})", pipeline.name, pipeline.label),
+        (false, true) => pipeline.name.clone(),
+        (true, false) => pipeline.label.clone(),
+        (true, true) => "(anonymous)".to_string(),
+    }
+}
+
+fn filter_title(filter: &Filter) -> String {
+    if filter.label.is_empty() {
+        filter.name.clone()
+    } else {
+        format!("{} {}", filter.name, filter.label)
+    }
+}
+
+/// escape for a double quoted label, shared by DOT and Mermaid
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "'")
+}
+
+/// POST /api/v1/graph
+pub async fn get_pipeline_graph(
+    client: &Client,
+    base_url: &Url,
+    script: &str,
+) -> Result<PipelineGraph, ApiError> {
+    let url = endpoint(base_url, "api/v1/graph")?;
+    let resp = send(
+        client.post(url).body(script.to_string()),
+        "get_pipeline_graph",
+        ResourceKind::Program,
+        "",
+    )
+    .await?;
+    let data = resp.bytes().await?;
+    tracing::debug!("get_pipeline_graph data: {:?}", data);
+    Ok(serde_json::from_slice(&data)?)
+}
+
+impl ApiClient {
+    /// pipelines defined by a script
+    pub async fn get_pipeline_graph(&self, script: &str) -> Result<PipelineGraph, ApiError> {
+        get_pipeline_graph(&self.client, &self.base_url, script).await
+    }
+
+    /// pipelines defined by every `.js` file of a codebase, including the ones inherited from its base.
+    /// files pending erase are left out
+    pub async fn get_codebase_layout(
+        &self,
+        codebase_name: &str,
+    ) -> Result<CodebaseLayout, ApiError> {
+        let codebase = self.get_codebase(codebase_name).await?;
+        let mut modules = vec![];
+        for file in module_files(&codebase)? {
+            // a derived codebase serves the inherited files of its base
+            let script = self.get_file(codebase_name, file.as_str()).await?;
+            let graph = self
+                .get_pipeline_graph(&String::from_utf8_lossy(&script))
+                .await?;
+            modules.push(ModuleLayout {
+                file: file.to_string(),
+                graph,
+            });
+        }
+        Ok(CodebaseLayout { modules })
+    }
+}
+
+/// the `.js` files of a codebase and of its base, sorted
+fn module_files(codebase: &Codebase) -> Result<Vec<RepoPath>, ApiError> {
+    let inherited = codebase.base_files.iter().map(|f| f.as_str());
+    let files: BTreeSet<RepoPath> = codebase
+        .current_files()
+        .into_iter()
+        .chain(inherited)
+        .filter(|f| f.ends_with(".js"))
+        .map(RepoPath::new)
+        .collect::<Result<_, _>>()?;
+    Ok(files.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> CodebaseLayout {
+        let graph = r#"
+            {
+                "pipelines": [
+                    {
+                        "name": "",
+                        "label": "listen 8080",
+                        "filters": [
+                            { "name": "demuxHTTP", "links": [1] },
+                            { "name": "dump", "label": "\"done\"" }
+                        ]
+                    },
+                    {
+                        "name": "",
+                        "label": "",
+                        "filters": [{ "name": "link", "links": ["api"] }]
+                    },
+                    {
+                        "name": "api",
+                        "filters": [{ "name": "serveHTTP" }]
+                    }
+                ],
+                "errors": []
+            }"#;
+        CodebaseLayout {
+            modules: vec![ModuleLayout {
+                file: "main.js".to_string(),
+                graph: serde_json::from_str(graph).unwrap(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_graph_serde() {
+        let layout = sample();
+        let graph = &layout.modules[0].graph;
+        assert_eq!(graph.pipelines.len(), 3);
+        assert_eq!(
+            graph.pipelines[0].filters[0].links,
+            vec![PipelineRef::Index(1)]
+        );
+        assert_eq!(
+            graph.resolve(&PipelineRef::Name("api".to_string())),
+            Some(2)
+        );
+        assert_eq!(graph.resolve(&PipelineRef::Index(9)), None);
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let dot = sample().to_dot();
+        assert!(dot.starts_with("digraph pipy {"));
+        assert!(dot.contains("label=\"main.js\";"));
+        assert!(dot.contains("label=\"listen 8080\";"));
+        assert!(dot.contains("m0_p0_f1 [label=\"dump 'done'\"];"));
+        assert!(dot.contains("m0_p0_f0 -> m0_p0_f1;"));
+        assert!(dot.contains("m0_p0_f0 -> m0_p1_f0 [style=dashed];"));
+        assert!(dot.contains("m0_p1_f0 -> m0_p2_f0 [style=dashed];"));
+        assert!(dot.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let mermaid = sample().to_mermaid();
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains("subgraph m0_p2[\"api\"]"));
+        assert!(mermaid.contains("m0_p1_f0[\"link\"]"));
+        assert!(mermaid.contains("m0_p0_f0 --> m0_p0_f1"));
+        assert!(mermaid.contains("m0_p1_f0 -.-> m0_p2_f0"));
+        assert_eq!(
+            mermaid.matches("subgraph").count(),
+            mermaid.matches("end\n").count()
+        );
+    }
+
+    #[test]
+    fn test_module_files() {
+        let derived = r#"
+            {
+                "version": "2",
+                "path": "/tenant",
+                "main": "/main.js",
+                "files": ["/main.js", "/old.js", "/tenant.js", "/config.json"],
+                "editFiles": [],
+                "erasedFiles": ["/old.js"],
+                "baseFiles": ["/main.js", "/lib/util.js"],
+                "derived": [],
+                "base": "/gateway",
+                "instances": {}
+            }"#;
+        let codebase = serde_json::from_str::<Codebase>(derived).unwrap();
+        let files: Vec<String> = module_files(&codebase)
+            .unwrap()
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(files, vec!["lib/util.js", "main.js", "tenant.js"]);
+    }
+}
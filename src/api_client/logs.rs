@@ -0,0 +1,298 @@
+//! Script logs of pipy, the ones defined with `new logging.TextLogger(name)` or `new logging.JSONLogger(name)`
+//! GET /api/v1/log lists the log names, GET /api/v1/log/[NAME] returns the recent lines,
+//! and a websocket on /api/v1/log/[NAME] pushes new lines as they are written
+use std::{collections::VecDeque, time::Duration};
+
+use futures::{stream, SinkExt, Stream, StreamExt};
+use reqwest::Url;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest, Message},
+    Connector, MaybeTlsStream, WebSocketStream,
+};
+
+use super::{
+    api::{endpoint, send, ApiError, ResourceKind},
+    ApiClient, RepoPath,
+};
+
+/// a line of a log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub log: String,
+    pub text: String,
+    /// the line was written before [`ApiClient::tail_log`] was called
+    pub history: bool,
+}
+
+/// options of [`ApiClient::tail_log_with`]
+#[derive(Debug, Clone)]
+pub struct TailOptions {
+    /// yield the recent lines kept by pipy before the new ones
+    pub backfill: bool,
+    /// delay before the first reconnect, doubled after each failure or close without a message
+    pub reconnect_delay: Duration,
+    pub max_reconnect_delay: Duration,
+}
+impl Default for TailOptions {
+    fn default() -> Self {
+        TailOptions {
+            backfill: true,
+            reconnect_delay: Duration::from_millis(500),
+            max_reconnect_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+struct TailState {
+    client: ApiClient,
+    name: String,
+    options: TailOptions,
+    backfilled: bool,
+    socket: Option<WebSocket>,
+    delay: Duration,
+    pending: VecDeque<LogLine>,
+}
+
+impl ApiClient {
+    /// GET /api/v1/log
+    pub async fn list_logs(&self) -> Result<Vec<String>, ApiError> {
+        let url = endpoint(&self.base_url, "api/v1/log")?;
+        let resp = send(self.client.get(url), "list_logs", ResourceKind::Log, "").await?;
+        let text = resp.text().await?;
+        if text.trim_start().starts_with('[') {
+            return Ok(serde_json::from_str(&text)?);
+        }
+        Ok(text
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| l.to_string())
+            .collect())
+    }
+
+    /// GET /api/v1/log/[NAME], the recent lines kept by pipy
+    pub async fn get_log(&self, name: &str) -> Result<Vec<String>, ApiError> {
+        let url = log_url(&self.base_url, name)?;
+        let resp = send(self.client.get(url), "get_log", ResourceKind::Log, name).await?;
+        Ok(resp.text().await?.lines().map(|l| l.to_string()).collect())
+    }
+
+    /// follow a log with the default [`TailOptions`]
+    pub fn tail_log(&self, name: &str) -> impl Stream<Item = Result<LogLine, ApiError>> {
+        self.tail_log_with(name, TailOptions::default())
+    }
+
+    /// follow a log, reconnecting when the websocket is closed, the stream never ends by itself.
+    /// an error fetching the history is yielded once, connection errors are retried.
+    /// reconnects back off until a message arrives, so a server closing right away isn't hammered
+    pub fn tail_log_with(
+        &self,
+        name: &str,
+        options: TailOptions,
+    ) -> impl Stream<Item = Result<LogLine, ApiError>> {
+        let state = TailState {
+            client: self.clone(),
+            name: name.to_string(),
+            backfilled: !options.backfill,
+            socket: None,
+            delay: options.reconnect_delay,
+            options,
+            pending: VecDeque::new(),
+        };
+        stream::unfold(state, |mut state| async move {
+            let item = state.next().await;
+            Some((item, state))
+        })
+    }
+
+    async fn connect_log(&self, name: &str) -> Result<WebSocket, ApiError> {
+        let url = log_socket_url(&self.base_url, name)?;
+        let mut request = url.as_str().into_client_request()?;
+        request.headers_mut().extend(self.headers.clone());
+        let connector = self.tls_connector.clone().map(Connector::NativeTls);
+        let (socket, resp) =
+            tokio_tungstenite::connect_async_tls_with_config(request, None, false, connector)
+                .await?;
+        tracing::debug!("connect_log: {:?}", resp);
+        Ok(socket)
+    }
+}
+
+impl TailState {
+    /// wait before reconnecting, each wait twice the previous one up to the max
+    async fn back_off(&mut self) {
+        tokio::time::sleep(self.delay).await;
+        self.delay = (self.delay * 2).min(self.options.max_reconnect_delay);
+    }
+
+    async fn next(&mut self) -> Result<LogLine, ApiError> {
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Ok(line);
+            }
+            if !self.backfilled {
+                self.backfilled = true;
+                let history = self.client.get_log(&self.name).await?;
+                self.pending.extend(history.into_iter().map(|text| LogLine {
+                    log: self.name.clone(),
+                    text,
+                    history: true,
+                }));
+                continue;
+            }
+            let Some(socket) = &mut self.socket else {
+                match self.client.connect_log(&self.name).await {
+                    Ok(socket) => self.socket = Some(socket),
+                    Err(e) => {
+                        tracing::warn!("tail_log {}: {}, retry in {:?}", self.name, e, self.delay);
+                        self.back_off().await;
+                    }
+                }
+                continue;
+            };
+            let text = match socket.next().await {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Binary(data))) => String::from_utf8_lossy(&data).to_string(),
+                Some(Ok(Message::Ping(data))) => {
+                    let _ = socket.send(Message::Pong(data)).await;
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                    tracing::debug!(
+                        "tail_log {}: websocket closed, reconnect in {:?}",
+                        self.name,
+                        self.delay
+                    );
+                    self.socket = None;
+                    self.back_off().await;
+                    continue;
+                }
+                Some(Ok(_)) => continue,
+            };
+            // the connection works, the next reconnect starts over from the first delay
+            self.delay = self.options.reconnect_delay;
+            self.pending.extend(text.lines().map(|line| LogLine {
+                log: self.name.clone(),
+                text: line.to_string(),
+                history: false,
+            }));
+        }
+    }
+}
+
+/// GET /api/v1/log/[NAME], the name is validated and percent-encoded like a file path
+fn log_url(base_url: &Url, name: &str) -> Result<Url, ApiError> {
+    endpoint(
+        base_url,
+        &format!("api/v1/log/{}", RepoPath::new(name)?.encoded()),
+    )
+}
+
+/// the websocket url of a log, `ws` for an `http` base url and `wss` for `https`
+fn log_socket_url(base_url: &Url, name: &str) -> Result<Url, ApiError> {
+    let mut url = log_url(base_url, name)?;
+    let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+    url.set_scheme(scheme)
+        .expect("http(s) urls can be changed to ws(s)");
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_log_socket_url() {
+        let base_url = Url::parse("https://127.0.0.1:6060/").unwrap();
+        assert_eq!(
+            log_socket_url(&base_url, "access").unwrap().as_str(),
+            "wss://127.0.0.1:6060/api/v1/log/access"
+        );
+        assert_eq!(
+            log_url(&base_url, "my log?#1").unwrap().as_str(),
+            "https://127.0.0.1:6060/api/v1/log/my%20log%3F%231"
+        );
+        assert!(matches!(
+            log_url(&base_url, "../repo"),
+            Err(ApiError::InvalidPath(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tail_log_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            // the first connection is closed after two lines, the second one stays open
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            socket
+                .send(Message::Text("line 1\nline 2".into()))
+                .await
+                .unwrap();
+            socket.close(None).await.unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            socket.send(Message::Text("line 3".into())).await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let client = ApiClient::new("127.0.0.1", port);
+        let options = TailOptions {
+            backfill: false,
+            reconnect_delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let lines: Vec<String> = client
+            .tail_log_with("access", options)
+            .take(3)
+            .map(|line| line.unwrap().text)
+            .collect()
+            .await;
+        assert_eq!(lines, vec!["line 1", "line 2", "line 3"]);
+    }
+
+    #[tokio::test]
+    async fn test_tail_log_close_backoff() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            // accepts and closes right away, like an unknown log name
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+                let _ = socket.close(None).await;
+            }
+        });
+
+        let client = ApiClient::new("127.0.0.1", port);
+        let options = TailOptions {
+            backfill: false,
+            reconnect_delay: Duration::from_millis(20),
+            max_reconnect_delay: Duration::from_secs(1),
+        };
+        let lines = client.tail_log_with("missing", options);
+        futures::pin_mut!(lines);
+        let next = tokio::time::timeout(Duration::from_millis(300), lines.next()).await;
+        assert!(next.is_err(), "no line expected: {:?}", next);
+        // 20 + 40 + 80 + 160ms of back-off, not a reconnect per close
+        let connections = connections.load(Ordering::SeqCst);
+        assert!(
+            (2..=5).contains(&connections),
+            "{} connections",
+            connections
+        );
+    }
+}
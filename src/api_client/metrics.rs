@@ -0,0 +1,358 @@
+//! Metrics of pipy, GET /api/v1/metrics
+//! pipy encodes metrics as a tree: the root node of a metric has its name in `k`, type in `t`,
+//! label names in `l` and bucket bounds of histograms in `b`, each child in `s` has a label value in `k`,
+//! `v` is the value of the node, a number, or bucket counts for histograms
+use std::collections::BTreeMap;
+
+use reqwest::{Client, Url};
+use serde::Deserialize;
+
+use super::api::{endpoint, send, ApiError, ResourceKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricValue {
+    Scalar(f64),
+    /// `counts[i]` is the number of samples `<= bounds[i]`, the extra last count is for `+Inf`
+    Histogram {
+        bounds: Vec<f64>,
+        counts: Vec<f64>,
+    },
+}
+
+/// one label set of a metric
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    pub labels: BTreeMap<String, String>,
+    pub value: MetricValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub kind: MetricKind,
+    pub label_names: Vec<String>,
+    /// value summed over all label sets
+    pub total: Option<MetricValue>,
+    /// values with a full label set
+    pub series: Vec<Series>,
+}
+impl Metric {
+    pub fn get(&self, labels: &[(&str, &str)]) -> Option<&MetricValue> {
+        self.series
+            .iter()
+            .find(|s| {
+                s.labels.len() == labels.len()
+                    && labels
+                        .iter()
+                        .all(|(k, v)| s.labels.get(*k).map(|l| l.as_str()) == Some(*v))
+            })
+            .map(|s| &s.value)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// milliseconds since unix epoch, if reported by pipy
+    pub timestamp: Option<f64>,
+    pub metrics: Vec<Metric>,
+}
+impl MetricsSnapshot {
+    pub fn get(&self, name: &str) -> Option<&Metric> {
+        self.metrics.iter().find(|m| m.name == name)
+    }
+
+    /// changes since `previous`: counters and histograms become the increments,
+    /// gauges keep their current value, series without change are dropped.
+    /// a counter or bucket lower than before means pipy restarted, the increment is then the current value.
+    /// pipy always reports values since start, so fetching incrementally is
+    /// keeping the last snapshot and diffing the next one against it
+    pub fn delta(&self, previous: &MetricsSnapshot) -> MetricsSnapshot {
+        let metrics = self
+            .metrics
+            .iter()
+            .filter_map(|metric| {
+                let prev = previous.get(&metric.name);
+                let series: Vec<Series> = metric
+                    .series
+                    .iter()
+                    .filter_map(|s| {
+                        let prev_value = prev.and_then(|p| {
+                            p.series
+                                .iter()
+                                .find(|ps| ps.labels == s.labels)
+                                .map(|ps| &ps.value)
+                        });
+                        if prev_value == Some(&s.value) {
+                            return None;
+                        }
+                        let value = match (metric.kind, prev_value) {
+                            (MetricKind::Gauge, _) | (_, None) => s.value.clone(),
+                            (_, Some(prev_value)) => subtract(&s.value, prev_value),
+                        };
+                        Some(Series {
+                            labels: s.labels.clone(),
+                            value,
+                        })
+                    })
+                    .collect();
+                if series.is_empty() {
+                    return None;
+                }
+                Some(Metric {
+                    series,
+                    total: None,
+                    ..metric.clone()
+                })
+            })
+            .collect();
+        MetricsSnapshot {
+            timestamp: self.timestamp,
+            metrics,
+        }
+    }
+}
+
+fn subtract(value: &MetricValue, prev: &MetricValue) -> MetricValue {
+    match (value, prev) {
+        (MetricValue::Scalar(v), MetricValue::Scalar(p)) if v >= p => MetricValue::Scalar(v - p),
+        (
+            MetricValue::Histogram { bounds, counts },
+            MetricValue::Histogram {
+                counts: prev_counts,
+                ..
+            },
+        ) if counts.len() == prev_counts.len()
+            && counts.iter().zip(prev_counts).all(|(c, p)| c >= p) =>
+        {
+            MetricValue::Histogram {
+                bounds: bounds.clone(),
+                counts: counts.iter().zip(prev_counts).map(|(c, p)| c - p).collect(),
+            }
+        }
+        // counted again from zero or bucket layout changed, e.g. pipy restarted
+        _ => value.clone(),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawMetrics {
+    List(Vec<RawMetric>),
+    Object {
+        #[serde(default)]
+        timestamp: Option<f64>,
+        metrics: Vec<RawMetric>,
+    },
+}
+
+#[derive(Deserialize)]
+struct RawMetric {
+    k: String,
+    #[serde(default)]
+    t: Option<String>,
+    #[serde(default)]
+    l: Vec<String>,
+    #[serde(default)]
+    b: Vec<f64>,
+    #[serde(default)]
+    v: Option<RawValue>,
+    #[serde(default)]
+    s: Vec<RawMetric>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawValue {
+    Scalar(f64),
+    Buckets(Vec<f64>),
+}
+
+/// decode the body of GET /api/v1/metrics
+pub fn parse_metrics(data: &[u8]) -> Result<MetricsSnapshot, ApiError> {
+    let (timestamp, raw) = match serde_json::from_slice::<RawMetrics>(data)? {
+        RawMetrics::List(metrics) => (None, metrics),
+        RawMetrics::Object { timestamp, metrics } => (timestamp, metrics),
+    };
+    let metrics = raw
+        .into_iter()
+        .map(|root| {
+            let kind = match root.t.as_deref().map(|t| t.to_ascii_lowercase()).as_deref() {
+                Some("counter") => MetricKind::Counter,
+                Some("histogram") => MetricKind::Histogram,
+                _ => MetricKind::Gauge,
+            };
+            let to_value = |v: &RawValue| match v {
+                RawValue::Scalar(v) => MetricValue::Scalar(*v),
+                RawValue::Buckets(counts) => MetricValue::Histogram {
+                    bounds: root.b.clone(),
+                    counts: counts.clone(),
+                },
+            };
+            let mut series = vec![];
+            // depth-first, carrying the label values on the path
+            let mut stack: Vec<(&RawMetric, Vec<&str>)> =
+                root.s.iter().rev().map(|s| (s, vec![])).collect();
+            while let Some((node, mut path)) = stack.pop() {
+                path.push(&node.k);
+                if node.s.is_empty() || path.len() >= root.l.len() {
+                    if let Some(v) = &node.v {
+                        let labels = root
+                            .l
+                            .iter()
+                            .zip(&path)
+                            .map(|(name, value)| (name.clone(), value.to_string()))
+                            .collect();
+                        series.push(Series {
+                            labels,
+                            value: to_value(v),
+                        });
+                    }
+                    continue;
+                }
+                stack.extend(node.s.iter().rev().map(|s| (s, path.clone())));
+            }
+            Metric {
+                name: root.k.clone(),
+                kind,
+                label_names: root.l.clone(),
+                total: root.v.as_ref().map(to_value),
+                series,
+            }
+        })
+        .collect();
+    Ok(MetricsSnapshot { timestamp, metrics })
+}
+
+/// GET /api/v1/metrics
+pub async fn get_metrics(client: &Client, base_url: &Url) -> Result<MetricsSnapshot, ApiError> {
+    let url = endpoint(base_url, "api/v1/metrics")?;
+    let resp = send(client.get(url), "get_metrics", ResourceKind::Program, "").await?;
+    let data = resp.bytes().await?;
+    parse_metrics(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METRICS: &str = r#"
+        {
+            "timestamp": 1718000000000,
+            "metrics": [
+                {
+                    "k": "pipy_inbound_count", "t": "Gauge", "l": ["listen", "peer"], "v": 3,
+                    "s": [
+                        { "k": "0.0.0.0:8080", "v": 3, "s": [
+                            { "k": "10.0.0.1", "v": 1 },
+                            { "k": "10.0.0.2", "v": 2 }
+                        ]}
+                    ]
+                },
+                {
+                    "k": "http_requests_total", "t": "Counter", "l": ["route"], "v": 10,
+                    "s": [{ "k": "/api", "v": 7 }, { "k": "/", "v": 3 }]
+                },
+                {
+                    "k": "http_latency", "t": "Histogram", "l": ["route"], "b": [10, 100],
+                    "v": [5, 3, 2],
+                    "s": [{ "k": "/api", "v": [5, 1, 1] }, { "k": "/", "v": [0, 2, 1] }]
+                }
+            ]
+        }"#;
+
+    #[test]
+    fn test_parse_metrics() {
+        let snapshot = parse_metrics(METRICS.as_bytes()).unwrap();
+        assert_eq!(snapshot.timestamp, Some(1718000000000.0));
+
+        let inbound = snapshot.get("pipy_inbound_count").unwrap();
+        assert_eq!(inbound.kind, MetricKind::Gauge);
+        assert_eq!(inbound.total, Some(MetricValue::Scalar(3.0)));
+        assert_eq!(inbound.series.len(), 2);
+        assert_eq!(
+            inbound.get(&[("listen", "0.0.0.0:8080"), ("peer", "10.0.0.2")]),
+            Some(&MetricValue::Scalar(2.0))
+        );
+
+        let latency = snapshot.get("http_latency").unwrap();
+        assert_eq!(latency.kind, MetricKind::Histogram);
+        assert_eq!(
+            latency.get(&[("route", "/")]),
+            Some(&MetricValue::Histogram {
+                bounds: vec![10.0, 100.0],
+                counts: vec![0.0, 2.0, 1.0]
+            })
+        );
+
+        // a bare list without timestamp
+        let snapshot = parse_metrics(br#"[{ "k": "up", "v": 1 }]"#).unwrap();
+        assert_eq!(snapshot.get("up").unwrap().series, vec![]);
+        assert_eq!(
+            snapshot.get("up").unwrap().total,
+            Some(MetricValue::Scalar(1.0))
+        );
+    }
+
+    #[test]
+    fn test_metrics_delta() {
+        let previous = parse_metrics(METRICS.as_bytes()).unwrap();
+        let current = parse_metrics(
+            METRICS
+                .replace(r#"{ "k": "/api", "v": 7 }"#, r#"{ "k": "/api", "v": 9 }"#)
+                .replace(r#""v": [0, 2, 1]"#, r#""v": [1, 2, 1]"#)
+                .as_bytes(),
+        )
+        .unwrap();
+        let delta = current.delta(&previous);
+        let names: Vec<&str> = delta.metrics.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["http_requests_total", "http_latency"]);
+        let requests = delta.get("http_requests_total").unwrap();
+        assert_eq!(requests.series.len(), 1);
+        assert_eq!(
+            requests.get(&[("route", "/api")]),
+            Some(&MetricValue::Scalar(2.0))
+        );
+        assert_eq!(
+            delta.get("http_latency").unwrap().get(&[("route", "/")]),
+            Some(&MetricValue::Histogram {
+                bounds: vec![10.0, 100.0],
+                counts: vec![1.0, 0.0, 0.0]
+            })
+        );
+    }
+
+    #[test]
+    fn test_metrics_delta_reset() {
+        let previous = parse_metrics(METRICS.as_bytes()).unwrap();
+        // pipy restarted and counted again from zero
+        let current = parse_metrics(
+            METRICS
+                .replace(r#"{ "k": "/api", "v": 7 }"#, r#"{ "k": "/api", "v": 3 }"#)
+                .replace(r#""v": [0, 2, 1]"#, r#""v": [1, 0, 0]"#)
+                .as_bytes(),
+        )
+        .unwrap();
+        let delta = current.delta(&previous);
+        assert_eq!(
+            delta
+                .get("http_requests_total")
+                .unwrap()
+                .get(&[("route", "/api")]),
+            Some(&MetricValue::Scalar(3.0))
+        );
+        assert_eq!(
+            delta.get("http_latency").unwrap().get(&[("route", "/")]),
+            Some(&MetricValue::Histogram {
+                bounds: vec![10.0, 100.0],
+                counts: vec![1.0, 0.0, 0.0]
+            })
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Codebase names and file paths in the urls of the admin api
+//!
+//! both are '/'-separated like `team/gateway` or `lib/util.js`, pipy shows them with a leading '/'.
+//! each segment is percent-encoded on its own, so spaces, `#` or `?` stay part of the name
+//! while the '/' between segments keep addressing nested codebases and files
+use std::{fmt, str::FromStr};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use super::api::ApiError;
+
+/// everything but the unreserved characters of RFC 3986
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// name of a codebase without the leading '/', such as `team/gateway`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CodebaseName(String);
+
+/// path of a file in a codebase without the leading '/', such as `lib/util.js`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RepoPath(String);
+
+impl CodebaseName {
+    /// a leading or trailing '/' is dropped, empty segments, `.`, `..` and control characters are rejected
+    pub fn new(name: &str) -> Result<Self, ApiError> {
+        validate(name.trim_end_matches('/'), name).map(CodebaseName)
+    }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+    /// the name as pipy shows it, `/team/gateway`
+    pub fn to_path(&self) -> String {
+        format!("/{}", self.0)
+    }
+    /// the name as a url path, each segment percent-encoded
+    pub fn encoded(&self) -> String {
+        encode(&self.0)
+    }
+}
+
+impl RepoPath {
+    /// a leading '/' is dropped, empty segments, `.`, `..` and control characters are rejected
+    pub fn new(path: &str) -> Result<Self, ApiError> {
+        validate(path, path).map(RepoPath)
+    }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+    /// the path as pipy shows it, `/lib/util.js`
+    pub fn to_path(&self) -> String {
+        format!("/{}", self.0)
+    }
+    /// the path as a url path, each segment percent-encoded
+    pub fn encoded(&self) -> String {
+        encode(&self.0)
+    }
+}
+
+/// `path` without its leading '/', for the names pipy reports such as [`super::api::Codebase::files`]
+pub(crate) fn trim_slash(path: &str) -> &str {
+    path.strip_prefix('/').unwrap_or(path)
+}
+
+fn validate(path: &str, input: &str) -> Result<String, ApiError> {
+    let path = trim_slash(path);
+    let valid = !path.is_empty()
+        && path.split('/').all(|segment| {
+            !matches!(segment, "" | "." | "..") && !segment.chars().any(char::is_control)
+        });
+    if !valid {
+        return Err(ApiError::InvalidPath(input.to_string()));
+    }
+    Ok(path.to_string())
+}
+
+fn encode(path: &str) -> String {
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+macro_rules! impl_path {
+    ($($ty:ident),*) => {$(
+        impl fmt::Display for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+        impl FromStr for $ty {
+            type Err = ApiError;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $ty::new(s)
+            }
+        }
+        impl TryFrom<&str> for $ty {
+            type Error = ApiError;
+            fn try_from(s: &str) -> Result<Self, Self::Error> {
+                $ty::new(s)
+            }
+        }
+        impl AsRef<str> for $ty {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+    )*};
+}
+impl_path!(CodebaseName, RepoPath);
+
+#[cfg(test)]
+mod tests {
+    use percent_encoding::percent_decode_str;
+    use reqwest::Url;
+
+    use super::*;
+
+    #[test]
+    fn test_codebase_name() {
+        let name = CodebaseName::new("/team/gateway/").unwrap();
+        assert_eq!(name.as_str(), "team/gateway");
+        assert_eq!(name.to_path(), "/team/gateway");
+        assert_eq!(name.encoded(), "team/gateway");
+        assert_eq!(
+            CodebaseName::new("my app #1?").unwrap().encoded(),
+            "my%20app%20%231%3F"
+        );
+        for invalid in ["", "/", "a//b", "../etc", "team/./a", "a\nb"] {
+            assert!(
+                matches!(CodebaseName::new(invalid), Err(ApiError::InvalidPath(p)) if p == invalid),
+                "{:?} should be invalid",
+                invalid
+            );
+        }
+        assert!(RepoPath::new("lib/").is_err());
+        assert!("lib/../main.js".parse::<RepoPath>().is_err());
+    }
+
+    #[test]
+    fn test_encoded_round_trip() {
+        let base_url = Url::parse("http://127.0.0.1:6060/").unwrap();
+        for name in [
+            "hello",
+            "team/gateway",
+            "my app #1?",
+            "50%/a&b=c",
+            "编码/ünïcode",
+            "semi;colon:+,@",
+        ] {
+            let name = CodebaseName::new(name).unwrap();
+            let file = RepoPath::new(&format!("{}/main.js", name)).unwrap();
+            let url = base_url
+                .join(&format!(
+                    "api/v1/repo-files/{}/{}",
+                    name.encoded(),
+                    file.encoded()
+                ))
+                .unwrap();
+            assert_eq!(url.query(), None, "{}", url);
+            assert_eq!(url.fragment(), None, "{}", url);
+            let segments: Vec<String> = url
+                .path_segments()
+                .unwrap()
+                .skip(3)
+                .map(|s| percent_decode_str(s).decode_utf8().unwrap().to_string())
+                .collect();
+            assert_eq!(segments.join("/"), format!("{}/{}", name, file), "{}", url);
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_nested_names_round_trip() {
+        let server = crate::mock::MockRepoServer::start().await.unwrap();
+        let client = server.client();
+
+        let name = "team/my gateway #1";
+        client.create_codebase("team").await.unwrap();
+        client.create_codebase(name).await.unwrap();
+        client
+            .update_file(name, "lib/a b?.js", b"export default {}".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(
+            client.get_codebase_list().await.unwrap(),
+            vec!["team", "team/my gateway #1"]
+        );
+        assert_eq!(
+            client.get_file(name, "/lib/a b?.js").await.unwrap(),
+            b"export default {}"
+        );
+        let codebase = client.get_codebase(name).await.unwrap();
+        assert_eq!(codebase.path, "/team/my gateway #1");
+        assert!(codebase.contains_file("lib/a b?.js"));
+        assert!(matches!(
+            client.get_file(name, "../team/main.js").await,
+            Err(ApiError::InvalidPath(_))
+        ));
+    }
+}
@@ -0,0 +1,162 @@
+//! Runtime status of pipy, GET /api/v1/status
+//! fields follow `Status::to_json` in `pipy/src/status.cpp`, missing fields are left as default
+use std::time::Duration;
+
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+use super::api::{endpoint, send, ApiError, ResourceKind};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PipyStatus {
+    /// milliseconds since unix epoch when the status was taken
+    pub timestamp: f64,
+    /// milliseconds since unix epoch when pipy started
+    pub since: f64,
+    pub uuid: String,
+    pub name: String,
+    /// codebase the program is loaded from
+    pub source: String,
+    /// version of the running codebase
+    pub version: String,
+    pub pools: Vec<PoolStatus>,
+    pub objects: Vec<ObjectStatus>,
+    pub chunks: Vec<ChunkStatus>,
+    pub buffers: Vec<BufferStatus>,
+    pub inbound: Vec<InboundStatus>,
+    pub outbound: Vec<OutboundStatus>,
+    #[serde(alias = "log")]
+    pub log_names: Vec<String>,
+}
+impl PipyStatus {
+    /// `None` if the timestamps are out of range
+    pub fn uptime(&self) -> Option<Duration> {
+        Duration::try_from_secs_f64((self.timestamp - self.since).max(0.0) / 1000.0).ok()
+    }
+    pub fn inbound_connections(&self) -> u64 {
+        self.inbound.iter().map(|i| i.connections).sum()
+    }
+    pub fn outbound_connections(&self) -> u64 {
+        self.outbound.iter().map(|o| o.connections).sum()
+    }
+}
+
+/// memory pool of a class of objects
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct PoolStatus {
+    pub name: String,
+    /// size of one object in bytes
+    pub size: u64,
+    pub allocated: u64,
+    pub pooled: u64,
+}
+
+/// live script objects of a class
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ObjectStatus {
+    #[serde(rename = "class")]
+    pub class_name: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ChunkStatus {
+    pub name: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct BufferStatus {
+    pub name: String,
+    /// buffered bytes
+    pub size: u64,
+}
+
+/// a listening port
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct InboundStatus {
+    pub protocol: String,
+    pub ip: String,
+    pub port: u16,
+    pub connections: u64,
+    /// bytes waiting to be sent
+    pub buffered: u64,
+}
+
+/// connections to an upstream peer
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OutboundStatus {
+    pub protocol: String,
+    pub peer: String,
+    pub connections: u64,
+    /// bytes waiting to be sent
+    pub buffered: u64,
+    /// milliseconds to establish a connection, the longest one
+    #[serde(alias = "max_connection_time")]
+    pub max_connection_time: f64,
+    /// milliseconds to establish a connection, the average
+    #[serde(alias = "avg_connection_time")]
+    pub avg_connection_time: f64,
+}
+
+/// GET /api/v1/status
+pub async fn get_status(client: &Client, base_url: &Url) -> Result<PipyStatus, ApiError> {
+    let url = endpoint(base_url, "api/v1/status")?;
+    let resp = send(client.get(url), "get_status", ResourceKind::Program, "").await?;
+    let data = resp.bytes().await?;
+    tracing::debug!("get_status data: {:?}", data);
+    Ok(serde_json::from_slice(&data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_serde() {
+        let json = r#"
+            {
+                "timestamp": 1718000060000,
+                "since": 1718000000000,
+                "uuid": "8b2c",
+                "name": "worker-1",
+                "source": "/hello",
+                "version": "2",
+                "pools": [{ "name": "Data", "size": 64, "allocated": 12, "pooled": 4 }],
+                "objects": [{ "class": "Message", "count": 3 }],
+                "chunks": [{ "name": "Data", "count": 10 }],
+                "buffers": [{ "name": "Pipeline", "size": 512 }],
+                "inbound": [
+                    { "protocol": "TCP", "ip": "0.0.0.0", "port": 8080, "connections": 2, "buffered": 0 },
+                    { "protocol": "TCP", "ip": "0.0.0.0", "port": 8443, "connections": 1, "buffered": 0 }
+                ],
+                "outbound": [
+                    { "protocol": "TCP", "peer": "10.0.0.2:80", "connections": 4, "buffered": 128,
+                      "maxConnectionTime": 12.5, "avgConnectionTime": 3.2 }
+                ],
+                "unknown": true
+            }"#;
+        let status: PipyStatus = serde_json::from_str(json).unwrap();
+        assert_eq!(status.uptime(), Some(Duration::from_secs(60)));
+        let overflow = PipyStatus {
+            timestamp: 1e300,
+            ..status.clone()
+        };
+        assert_eq!(overflow.uptime(), None);
+        assert_eq!(status.inbound_connections(), 3);
+        assert_eq!(status.outbound_connections(), 4);
+        assert_eq!(status.objects[0].class_name, "Message");
+        assert_eq!(status.outbound[0].max_connection_time, 12.5);
+
+        // an idle instance may omit everything
+        let status: PipyStatus = serde_json::from_str("{}").unwrap();
+        assert!(status.inbound.is_empty());
+    }
+}
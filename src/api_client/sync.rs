@@ -0,0 +1,236 @@
+//! Sync a local directory to a codebase
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    api::{ApiError, CodebaseVersion},
+    path::trim_slash,
+    ApiClient, RepoPath,
+};
+
+/// options of [`ApiClient::sync_dir`]
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// delete remote files that don't exist in the local directory
+    pub delete_missing: bool,
+    /// entry script to set after uploading, relative to the directory
+    pub main: Option<String>,
+    /// publish the changes if anything changed
+    pub publish: bool,
+}
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            delete_missing: false,
+            main: None,
+            publish: true,
+        }
+    }
+}
+
+/// what [`ApiClient::sync_dir`] changed, file names are relative to the codebase root
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncReport {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+    pub unchanged: Vec<String>,
+    /// set if the entry script was changed
+    pub main: Option<String>,
+    /// version after publishing, `None` if nothing was published
+    pub published_version: Option<CodebaseVersion>,
+}
+impl SyncReport {
+    pub fn is_changed(&self) -> bool {
+        !self.added.is_empty()
+            || !self.updated.is_empty()
+            || !self.deleted.is_empty()
+            || self.main.is_some()
+    }
+}
+
+impl ApiClient {
+    /// upload the files of directory `path` whose content differs from the codebase.
+    /// hidden entries such as `.git` or the [`super::MANIFEST_FILE`] of an export are skipped,
+    /// symlinks to files are read but symlinked directories aren't descended into.
+    /// the changes are applied as a [`super::CodebaseTransaction`]: on failure the previous contents
+    /// are written back, but they stay in `edit_files` until the next publish, and a publish by
+    /// somebody else before the revert publishes the half-applied changes
+    pub async fn sync_dir(
+        &self,
+        codebase_name: &str,
+        path: impl AsRef<Path>,
+        options: &SyncOptions,
+    ) -> Result<SyncReport, ApiError> {
+        let local = read_dir_recursive(path.as_ref())?;
+        let codebase = self.get_codebase(codebase_name).await?;
+        let remote = codebase.current_files();
+
+        let mut report = SyncReport::default();
+        let mut transaction = self.transaction(codebase_name);
+        for (file_name, data) in &local {
+            if !remote.contains(&file_name.as_str()) {
+                transaction.write(file_name, data.clone());
+                report.added.push(file_name.clone());
+                continue;
+            }
+            let remote_data = self.get_file(codebase_name, file_name).await?;
+            if *data == remote_data {
+                report.unchanged.push(file_name.clone());
+            } else {
+                transaction.write(file_name, data.clone());
+                report.updated.push(file_name.clone());
+            }
+        }
+        if options.delete_missing {
+            for file_name in remote.iter().filter(|f| !local.contains_key(**f)) {
+                transaction.delete(file_name);
+                report.deleted.push(file_name.to_string());
+            }
+        }
+        if let Some(main) = &options.main {
+            let main = RepoPath::new(main)?;
+            if trim_slash(&codebase.main) != main.as_str() {
+                transaction.set_main(main.as_str());
+                report.main = Some(main.to_string());
+            }
+        }
+        tracing::debug!("sync_dir {}: {:?}", codebase_name, report);
+
+        if transaction.is_empty() {
+            return Ok(report);
+        }
+        if options.publish {
+            report.published_version = Some(transaction.commit().await?);
+        } else {
+            transaction.apply().await?;
+        }
+        Ok(report)
+    }
+}
+
+/// read all files under `root`, keyed by their path relative to `root` joined with '/'.
+/// hidden entries are skipped, symlinks are followed to files only so a link loop can't recurse
+fn read_dir_recursive(root: &Path) -> Result<BTreeMap<String, Vec<u8>>, ApiError> {
+    let mut files = BTreeMap::new();
+    let mut dirs: Vec<PathBuf> = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            // also covers the manifest `export_codebase` writes next to the files
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if file_type.is_symlink() && !path.is_file() {
+                tracing::debug!("sync_dir skips {}", path.display());
+                continue;
+            }
+            let relative = path
+                .strip_prefix(root)
+                .expect("walked path is under root")
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.insert(relative, std::fs::read(&path)?);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_dir_recursive() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("lib/nested")).unwrap();
+        std::fs::write(dir.path().join("main.js"), "main").unwrap();
+        std::fs::write(dir.path().join("lib/util.js"), "util").unwrap();
+        std::fs::write(dir.path().join("lib/nested/deep.js"), "deep").unwrap();
+
+        let files = read_dir_recursive(dir.path()).unwrap();
+        let names: Vec<&str> = files.keys().map(|k| k.as_str()).collect();
+        assert_eq!(names, vec!["lib/nested/deep.js", "lib/util.js", "main.js"]);
+        assert_eq!(files["lib/util.js"], b"util");
+    }
+
+    #[test]
+    fn test_read_dir_recursive_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join(".git")).unwrap();
+        std::fs::create_dir_all(dir.path().join("lib")).unwrap();
+        std::fs::write(dir.path().join("main.js"), "main").unwrap();
+        std::fs::write(dir.path().join(".git/config"), "").unwrap();
+        std::fs::write(dir.path().join(crate::api_client::MANIFEST_FILE), "{}").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            symlink(dir.path(), dir.path().join("lib/loop")).unwrap();
+            symlink(dir.path().join("main.js"), dir.path().join("lib/main.js")).unwrap();
+        }
+
+        let files = read_dir_recursive(dir.path()).unwrap();
+        let names: Vec<&str> = files.keys().map(|k| k.as_str()).collect();
+        #[cfg(unix)]
+        assert_eq!(names, vec!["lib/main.js", "main.js"]);
+        #[cfg(not(unix))]
+        assert_eq!(names, vec!["main.js"]);
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_sync_dir() {
+        let server = crate::mock::MockRepoServer::start().await.unwrap();
+        let client = server.client();
+        client.create_codebase("hello").await.unwrap();
+        for (file_name, data) in [("main.js", "main"), ("old.js", "old"), ("erased.js", "x")] {
+            client
+                .update_file("hello", file_name, data.as_bytes().to_vec())
+                .await
+                .unwrap();
+        }
+        client.publish_changes("hello").await.unwrap();
+        // pending erase, neither fetched nor deleted again
+        client.delete_file("hello", "erased.js").await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("lib")).unwrap();
+        std::fs::write(dir.path().join("main.js"), "main").unwrap();
+        std::fs::write(dir.path().join("lib/util.js"), "util").unwrap();
+        let options = SyncOptions {
+            delete_missing: true,
+            main: Some("/lib/util.js".to_string()),
+            publish: true,
+        };
+        let report = client
+            .sync_dir("hello", dir.path(), &options)
+            .await
+            .unwrap();
+        assert_eq!(report.added, vec!["lib/util.js"]);
+        assert_eq!(report.unchanged, vec!["main.js"]);
+        assert_eq!(report.deleted, vec!["old.js"]);
+        assert_eq!(report.main.as_deref(), Some("lib/util.js"));
+        assert!(report.published_version.is_some());
+
+        let codebase = client.get_codebase("hello").await.unwrap();
+        assert_eq!(codebase.main, "/lib/util.js");
+        assert_eq!(codebase.current_files(), vec!["lib/util.js", "main.js"]);
+
+        let report = client
+            .sync_dir("hello", dir.path(), &options)
+            .await
+            .unwrap();
+        assert!(!report.is_changed());
+        assert_eq!(report.published_version, None);
+    }
+}
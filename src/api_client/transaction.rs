@@ -0,0 +1,266 @@
+//! Stage several file changes of a codebase and apply them as a whole
+//!
+//! pipy edits one file per request, so a failure in the middle leaves the codebase half-edited.
+//! a transaction saves the current contents of the files it touches, applies the changes
+//! concurrently, and writes the saved contents back if any of them fails.
+//! pipy has no way to drop a pending edit, so a reverted file keeps its content
+//! but stays in `edit_files` until the next publish
+use std::collections::BTreeMap;
+
+use futures::future;
+
+use super::{
+    api::{ApiError, CodebaseUpdate, CodebaseVersion},
+    path::trim_slash,
+    ApiClient,
+};
+
+#[derive(Debug, Clone)]
+enum Change {
+    Write(Vec<u8>),
+    Delete,
+}
+
+/// file changes of a codebase to apply together, created by [`ApiClient::transaction`]
+#[derive(Clone)]
+pub struct CodebaseTransaction {
+    client: ApiClient,
+    codebase_name: String,
+    changes: BTreeMap<String, Change>,
+    main: Option<String>,
+}
+
+impl ApiClient {
+    /// start staging changes of the codebase, nothing is sent until it is applied
+    pub fn transaction(&self, codebase_name: &str) -> CodebaseTransaction {
+        CodebaseTransaction {
+            client: self.clone(),
+            codebase_name: codebase_name.to_string(),
+            changes: BTreeMap::new(),
+            main: None,
+        }
+    }
+}
+
+impl CodebaseTransaction {
+    /// create or overwrite a file, replacing any change staged for it
+    pub fn write(&mut self, file_name: &str, data: Vec<u8>) -> &mut Self {
+        self.changes
+            .insert(trim_slash(file_name).to_string(), Change::Write(data));
+        self
+    }
+    /// delete a file, replacing any change staged for it
+    pub fn delete(&mut self, file_name: &str) -> &mut Self {
+        self.changes
+            .insert(trim_slash(file_name).to_string(), Change::Delete);
+        self
+    }
+    /// set the entry script once the files are changed
+    pub fn set_main(&mut self, main: &str) -> &mut Self {
+        self.main = Some(trim_slash(main).to_string());
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.main.is_none()
+    }
+
+    /// apply the changes and publish them, fails with [`ApiError::Conflict`] if somebody
+    /// published since the transaction started. the changes are reverted on any failure.
+    ///
+    /// this is not isolated from other clients: if somebody else publishes between applying the
+    /// changes and reverting them, the half-applied changes are published with their version
+    pub async fn commit(self) -> Result<CodebaseVersion, ApiError> {
+        let client = self.client.clone();
+        let name = self.codebase_name.clone();
+        let applied = self.apply_changes().await?;
+        match client.publish_changes_if(&name, &applied.version).await {
+            Ok(version) => Ok(version),
+            Err(e) => Err(applied.revert(e).await),
+        }
+    }
+
+    /// apply the changes without publishing, they are reverted if any of them fails
+    pub async fn apply(self) -> Result<(), ApiError> {
+        self.apply_changes().await.map(|_| ())
+    }
+
+    async fn apply_changes(self) -> Result<Applied, ApiError> {
+        let client = &self.client;
+        let name = self.codebase_name.as_str();
+        let codebase = client.get_codebase(name).await?;
+        let previous = future::try_join_all(self.changes.keys().map(|file_name| async move {
+            match client.get_file(name, file_name).await {
+                Ok(data) => Ok((file_name.clone(), Some(data))),
+                Err(e) if e.is_not_found() => Ok((file_name.clone(), None)),
+                Err(e) => Err(e),
+            }
+        }))
+        .await?;
+        let mut applied = Applied {
+            client: client.clone(),
+            codebase_name: name.to_string(),
+            version: codebase.version,
+            previous,
+            main: None,
+        };
+
+        let results = future::join_all(self.changes.iter().map(|(file_name, change)| {
+            let file_name = file_name.as_str();
+            async move {
+                match change {
+                    Change::Write(data) => client.update_file(name, file_name, data.clone()).await,
+                    Change::Delete => client.delete_file(name, file_name).await,
+                }
+            }
+        }))
+        .await;
+        if let Some(e) = results.into_iter().find_map(Result::err) {
+            return Err(applied.revert(e).await);
+        }
+
+        if let Some(main) = &self.main {
+            let update = CodebaseUpdate {
+                main: Some(main.clone()),
+                ..Default::default()
+            };
+            if let Err(e) = client.update_codebase(name, &update).await {
+                return Err(applied.revert(e).await);
+            }
+            applied.main = Some(trim_slash(&codebase.main).to_string());
+        }
+        Ok(applied)
+    }
+}
+
+/// changes sent to pipy, with what is needed to undo them
+struct Applied {
+    client: ApiClient,
+    codebase_name: String,
+    version: CodebaseVersion,
+    /// contents before the transaction, `None` if the file didn't exist
+    previous: Vec<(String, Option<Vec<u8>>)>,
+    /// entry script before the transaction, if it was changed
+    main: Option<String>,
+}
+
+impl Applied {
+    /// undo the changes after `error`, returns the error to report.
+    /// files are written back as new edits, they stay in `edit_files` with their previous content
+    async fn revert(self, error: ApiError) -> ApiError {
+        let client = &self.client;
+        let name = self.codebase_name.as_str();
+        tracing::debug!("revert transaction of {}: {}", name, error);
+
+        let mut failed: Vec<String> = vec![];
+        if let Some(main) = &self.main {
+            let update = CodebaseUpdate {
+                main: Some(main.clone()),
+                ..Default::default()
+            };
+            if let Err(e) = client.update_codebase(name, &update).await {
+                tracing::warn!("revert main of {}: {}", name, e);
+                failed.push(main.clone());
+            }
+        }
+        let results = future::join_all(self.previous.iter().map(|(file_name, data)| async move {
+            let result = match data {
+                Some(data) => client.update_file(name, file_name, data.clone()).await,
+                // the change may have failed before creating the file
+                None => match client.delete_file(name, file_name).await {
+                    Err(e) if e.is_not_found() => Ok(()),
+                    result => result,
+                },
+            };
+            (file_name, result)
+        }))
+        .await;
+        for (file_name, result) in results {
+            if let Err(e) = result {
+                tracing::warn!("revert {} of {}: {}", file_name, name, e);
+                failed.push(file_name.clone());
+            }
+        }
+
+        if failed.is_empty() {
+            error
+        } else {
+            ApiError::RevertFailed {
+                error: Box::new(error),
+                files: failed,
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use crate::mock::MockRepoServer;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let server = MockRepoServer::start().await.unwrap();
+        let client = server.client();
+        client.create_codebase("hello").await.unwrap();
+        client
+            .update_file("hello", "old.js", b"old".to_vec())
+            .await
+            .unwrap();
+        let version = client.publish_changes("hello").await.unwrap();
+
+        let mut transaction = client.transaction("hello");
+        transaction
+            .write("main.js", b"main".to_vec())
+            .write("util.js", b"util".to_vec())
+            .delete("old.js")
+            .set_main("util.js");
+        let published = transaction.commit().await.unwrap();
+        assert_eq!(version.next(), Some(published));
+        let codebase = client.get_codebase("hello").await.unwrap();
+        assert_eq!(codebase.files, vec!["/main.js", "/util.js"]);
+        assert_eq!(codebase.main, "/util.js");
+        assert_eq!(client.get_file("hello", "main.js").await.unwrap(), b"main");
+    }
+
+    #[tokio::test]
+    async fn test_transaction_revert() {
+        let server = MockRepoServer::start().await.unwrap();
+        let client = server.client();
+        client.create_codebase("hello").await.unwrap();
+        let main_js = client.get_file("hello", "main.js").await.unwrap();
+
+        // deleting a missing file fails after the other changes are sent
+        let mut transaction = client.transaction("hello");
+        transaction
+            .write("main.js", b"changed".to_vec())
+            .write("util.js", b"util".to_vec())
+            .delete("missing.js");
+        let err = transaction.commit().await.unwrap_err();
+        assert!(err.is_not_found(), "{:?}", err);
+        assert_eq!(client.get_file("hello", "main.js").await.unwrap(), main_js);
+        assert!(client
+            .get_file("hello", "util.js")
+            .await
+            .unwrap_err()
+            .is_not_found());
+        let codebase = client.get_codebase("hello").await.unwrap();
+        assert_eq!(codebase.version, CodebaseVersion::new("0"));
+        // the previous content is back, but as a pending edit
+        assert_eq!(codebase.edit_files, vec!["/main.js"]);
+
+        // so does a main that doesn't exist
+        let mut transaction = client.transaction("hello");
+        transaction
+            .write("util.js", b"util".to_vec())
+            .set_main("missing.js");
+        assert!(transaction.apply().await.is_err());
+        assert!(client
+            .get_file("hello", "util.js")
+            .await
+            .unwrap_err()
+            .is_not_found());
+        let codebase = client.get_codebase("hello").await.unwrap();
+        assert_eq!(codebase.main, "/main.js");
+    }
+}
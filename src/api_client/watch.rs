@@ -0,0 +1,386 @@
+//! Poll a codebase for published changes
+//!
+//! GET /api/v1/repo/[CODEBASE] is sent with `If-None-Match` once the server returned an `ETag`,
+//! so an unchanged codebase costs a 304 without body. file contents are only fetched
+//! when a new version is seen, to tell the edited files from the untouched ones
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    time::Duration,
+};
+
+use futures::{future, stream, Stream};
+use reqwest::{
+    header::{ETAG, IF_NONE_MATCH},
+    StatusCode,
+};
+use sha2::{Digest, Sha256};
+
+use super::{
+    api::{repo_url, send, ApiError, Codebase, CodebaseVersion, ResourceKind},
+    path::trim_slash,
+    ApiClient,
+};
+
+/// a change of a watched codebase, see [`ApiClient::watch_codebase`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodebaseEvent {
+    /// a new version was published, followed by the file events of the version
+    Published {
+        from: CodebaseVersion,
+        to: CodebaseVersion,
+    },
+    FilesAdded(Vec<String>),
+    FilesRemoved(Vec<String>),
+    /// files whose content changed
+    FilesEdited(Vec<String>),
+    MainChanged {
+        from: String,
+        to: String,
+    },
+}
+
+/// what is known of the codebase since the last poll, file names without leading '/'
+#[derive(Debug)]
+struct Seen {
+    version: CodebaseVersion,
+    main: String,
+    /// sha256 of the published content, `None` for files with pending edits or erases:
+    /// GET returns the unpublished content, so it isn't known until the next publish
+    files: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+struct WatchState {
+    client: ApiClient,
+    name: String,
+    interval: Duration,
+    etag: Option<String>,
+    seen: Option<Seen>,
+    polled: bool,
+    pending: VecDeque<CodebaseEvent>,
+}
+
+impl ApiClient {
+    /// poll the codebase every `interval` and yield its changes, the stream never ends by itself.
+    /// the first poll only records the current state, errors are yielded and polling goes on
+    pub fn watch_codebase(
+        &self,
+        codebase_name: &str,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<CodebaseEvent, ApiError>> {
+        let state = WatchState {
+            client: self.clone(),
+            name: codebase_name.to_string(),
+            interval,
+            etag: None,
+            seen: None,
+            polled: false,
+            pending: VecDeque::new(),
+        };
+        stream::unfold(state, |mut state| async move {
+            let item = state.next().await;
+            Some((item, state))
+        })
+    }
+
+    /// GET /api/v1/repo/[CODEBASE] with `If-None-Match: etag`,
+    /// `None` if not modified, otherwise the codebase with its `ETag` if any
+    async fn get_codebase_if_changed(
+        &self,
+        codebase_name: &str,
+        etag: Option<&str>,
+    ) -> Result<Option<(Codebase, Option<String>)>, ApiError> {
+        let url = repo_url(&self.base_url, codebase_name)?;
+        let mut request = self.client.get(url);
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let resp = match send(
+            request,
+            "get_codebase_if_changed",
+            ResourceKind::Codebase,
+            codebase_name,
+        )
+        .await
+        {
+            Err(ApiError::UnexpectedStatus {
+                status: StatusCode::NOT_MODIFIED,
+                ..
+            }) => return Ok(None),
+            resp => resp?,
+        };
+        let etag = resp
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let codebase = serde_json::from_slice(&resp.bytes().await?)?;
+        Ok(Some((codebase, etag)))
+    }
+}
+
+impl WatchState {
+    async fn next(&mut self) -> Result<CodebaseEvent, ApiError> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            if self.polled {
+                tokio::time::sleep(self.interval).await;
+            }
+            self.polled = true;
+            let Some((codebase, etag)) = self
+                .client
+                .get_codebase_if_changed(&self.name, self.etag.as_deref())
+                .await?
+            else {
+                continue;
+            };
+            let new = self.fetch(&codebase, self.seen.as_ref()).await?;
+            if let Some(old) = &self.seen {
+                self.pending.extend(diff(old, &new));
+            }
+            self.seen = Some(new);
+            self.etag = etag;
+        }
+    }
+
+    /// the state of `codebase`, contents are fetched again only for a new version.
+    /// a file removed before its content is fetched is left out
+    async fn fetch(&self, codebase: &Codebase, old: Option<&Seen>) -> Result<Seen, ApiError> {
+        let files = match old {
+            Some(old) if old.version == codebase.version => old.files.clone(),
+            _ => {
+                let pending: BTreeSet<&str> = codebase
+                    .edit_files
+                    .iter()
+                    .chain(&codebase.erased_files)
+                    .map(|f| trim_slash(f))
+                    .collect();
+                let pending = &pending;
+                let hashes = codebase.files.iter().map(|file| async move {
+                    let file = trim_slash(file);
+                    if pending.contains(file) {
+                        return Ok(Some((file.to_string(), None)));
+                    }
+                    match self.client.get_file(&self.name, file).await {
+                        Ok(data) => Ok(Some((
+                            file.to_string(),
+                            Some(Sha256::digest(data).to_vec()),
+                        ))),
+                        Err(e) if e.is_not_found() => Ok(None),
+                        Err(e) => Err(e),
+                    }
+                });
+                future::try_join_all(hashes)
+                    .await?
+                    .into_iter()
+                    .flatten()
+                    .collect()
+            }
+        };
+        Ok(Seen {
+            version: codebase.version.clone(),
+            main: trim_slash(&codebase.main).to_string(),
+            files,
+        })
+    }
+}
+
+/// the events from `old` to `new`, file events only come with a new version.
+/// a file whose published content wasn't known in `old` counts as edited, while one whose content
+/// isn't known in `new` (a pending edit or erase again) is left out of `FilesEdited`:
+/// whether its published content changed is only told by a later version
+fn diff(old: &Seen, new: &Seen) -> Vec<CodebaseEvent> {
+    let mut events = vec![];
+    if old.version != new.version {
+        events.push(CodebaseEvent::Published {
+            from: old.version.clone(),
+            to: new.version.clone(),
+        });
+        let added: Vec<String> = new
+            .files
+            .keys()
+            .filter(|f| !old.files.contains_key(*f))
+            .cloned()
+            .collect();
+        let removed: Vec<String> = old
+            .files
+            .keys()
+            .filter(|f| !new.files.contains_key(*f))
+            .cloned()
+            .collect();
+        let edited: Vec<String> = new
+            .files
+            .iter()
+            .filter(|(f, hash)| {
+                hash.is_some()
+                    && old
+                        .files
+                        .get(*f)
+                        .is_some_and(|old| old.is_none() || old != *hash)
+            })
+            .map(|(f, _)| f.clone())
+            .collect();
+        if !added.is_empty() {
+            events.push(CodebaseEvent::FilesAdded(added));
+        }
+        if !removed.is_empty() {
+            events.push(CodebaseEvent::FilesRemoved(removed));
+        }
+        if !edited.is_empty() {
+            events.push(CodebaseEvent::FilesEdited(edited));
+        }
+    }
+    if old.main != new.main {
+        events.push(CodebaseEvent::MainChanged {
+            from: old.main.clone(),
+            to: new.main.clone(),
+        });
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let seen = |version: &str, main: &str, files: &[(&str, &str)]| Seen {
+            version: CodebaseVersion::new(version),
+            main: main.to_string(),
+            files: files
+                .iter()
+                .map(|(f, data)| (f.to_string(), Some(Sha256::digest(data).to_vec())))
+                .collect(),
+        };
+        let old = seen("1", "main.js", &[("main.js", "a"), ("util.js", "b")]);
+        assert!(diff(&old, &old).is_empty());
+
+        let new = seen("2", "app.js", &[("main.js", "a2"), ("app.js", "c")]);
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                CodebaseEvent::Published {
+                    from: CodebaseVersion::new("1"),
+                    to: CodebaseVersion::new("2"),
+                },
+                CodebaseEvent::FilesAdded(vec!["app.js".to_string()]),
+                CodebaseEvent::FilesRemoved(vec!["util.js".to_string()]),
+                CodebaseEvent::FilesEdited(vec!["main.js".to_string()]),
+                CodebaseEvent::MainChanged {
+                    from: "main.js".to_string(),
+                    to: "app.js".to_string(),
+                },
+            ]
+        );
+
+        // pending edits: unknown before counts as edited, unknown after isn't reported
+        let mut old = seen("2", "main.js", &[("main.js", "a"), ("util.js", "b")]);
+        old.files.insert("lib.js".to_string(), None);
+        let mut new = seen("3", "main.js", &[("lib.js", "c"), ("util.js", "b")]);
+        new.files.insert("main.js".to_string(), None);
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                CodebaseEvent::Published {
+                    from: CodebaseVersion::new("2"),
+                    to: CodebaseVersion::new("3"),
+                },
+                CodebaseEvent::FilesEdited(vec!["lib.js".to_string()]),
+            ]
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_watch_codebase() {
+        use futures::StreamExt;
+
+        use crate::mock::MockRepoServer;
+
+        let server = MockRepoServer::start().await.unwrap();
+        let client = server.client();
+        client.create_codebase("hello").await.unwrap();
+
+        let (_, etag) = client
+            .get_codebase_if_changed("hello", None)
+            .await
+            .unwrap()
+            .unwrap();
+        let etag = etag.expect("codebase without etag");
+        assert!(client
+            .get_codebase_if_changed("hello", Some(&etag))
+            .await
+            .unwrap()
+            .is_none());
+
+        let events = client.watch_codebase("hello", Duration::from_millis(10));
+        futures::pin_mut!(events);
+        // let the first poll record the current state
+        let first = tokio::time::timeout(Duration::from_millis(50), events.next()).await;
+        assert!(first.is_err(), "no change yet: {:?}", first);
+
+        client
+            .update_file("hello", "main.js", b"pipy().listen(8080)".to_vec())
+            .await
+            .unwrap();
+        client
+            .update_file("hello", "util.js", b"export default {}".to_vec())
+            .await
+            .unwrap();
+        let version = client.publish_changes("hello").await.unwrap();
+        let events: Vec<CodebaseEvent> = events.take(3).map(|event| event.unwrap()).collect().await;
+        assert_eq!(
+            events,
+            vec![
+                CodebaseEvent::Published {
+                    from: CodebaseVersion::new("0"),
+                    to: version,
+                },
+                CodebaseEvent::FilesAdded(vec!["util.js".to_string()]),
+                CodebaseEvent::FilesEdited(vec!["main.js".to_string()]),
+            ]
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_watch_pending_changes() {
+        use futures::StreamExt;
+
+        use crate::mock::MockRepoServer;
+
+        let server = MockRepoServer::start().await.unwrap();
+        let client = server.client();
+        client.create_codebase("hello").await.unwrap();
+        client
+            .update_file("hello", "util.js", b"export default {}".to_vec())
+            .await
+            .unwrap();
+        client.publish_changes("hello").await.unwrap();
+        // pending when the watch starts: GET util.js is a 404, GET main.js the unpublished content
+        client.delete_file("hello", "util.js").await.unwrap();
+        client
+            .update_file("hello", "main.js", b"pipy().listen(8080)".to_vec())
+            .await
+            .unwrap();
+
+        let events = client.watch_codebase("hello", Duration::from_millis(10));
+        futures::pin_mut!(events);
+        let first = tokio::time::timeout(Duration::from_millis(50), events.next()).await;
+        assert!(first.is_err(), "no change yet: {:?}", first);
+
+        let from = client.get_codebase("hello").await.unwrap().version;
+        let to = client.publish_changes("hello").await.unwrap();
+        let events: Vec<CodebaseEvent> = events.take(3).map(|event| event.unwrap()).collect().await;
+        assert_eq!(
+            events,
+            vec![
+                CodebaseEvent::Published { from, to },
+                CodebaseEvent::FilesRemoved(vec!["util.js".to_string()]),
+                CodebaseEvent::FilesEdited(vec!["main.js".to_string()]),
+            ]
+        );
+    }
+}
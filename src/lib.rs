@@ -1,5 +1,8 @@
 /// a test demo for pipy
+#[cfg(feature = "libpipy")]
 use libc::{c_char, c_int};
+use std::path::PathBuf;
+#[cfg(feature = "libpipy")]
 use std::{
     ffi::CString,
     sync::{atomic, Arc},
@@ -7,6 +10,10 @@ use std::{
 };
 
 pub mod api_client;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod pipeline;
+pub mod testing;
 mod util;
 
 #[cfg(feature = "use_tcmalloc")]
@@ -17,6 +24,7 @@ use tcmalloc::TCMalloc;
 #[global_allocator]
 static GLOBAL: TCMalloc = TCMalloc;
 
+#[cfg(feature = "libpipy")]
 #[link(name = "pipy", kind = "dylib")]
 extern "C" {
     pub fn pipy_main(argc: c_int, argv: *const *const c_char) -> c_int;
@@ -24,6 +32,7 @@ extern "C" {
     pub fn pipy_exit(force: c_int);
 }
 /// start pipy in repo mode with given port, default port is 6060
+#[cfg(feature = "libpipy")]
 pub fn start_pipy_repo(port: Option<u16>) -> PipyRepo {
     let port = port.unwrap_or(6060);
     let pipy = PipyRepo::new(port);
@@ -31,28 +40,82 @@ pub fn start_pipy_repo(port: Option<u16>) -> PipyRepo {
     pipy
 }
 
+/// options to start pipy in repo mode
+#[derive(Clone, Debug)]
+pub struct PipyConfig {
+    pub admin_port: u16,
+    /// serve the admin api over https, see [`AdminTls`]
+    pub admin_tls: Option<AdminTls>,
+}
+impl PipyConfig {
+    pub fn new(admin_port: u16) -> Self {
+        PipyConfig {
+            admin_port,
+            admin_tls: None,
+        }
+    }
+    pub fn admin_tls(mut self, admin_tls: AdminTls) -> Self {
+        self.admin_tls = Some(admin_tls);
+        self
+    }
+    /// command line arguments passed to `pipy_main`, without the program name
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![format!("--admin-port={}", self.admin_port)];
+        if let Some(tls) = &self.admin_tls {
+            args.push(format!("--admin-tls-cert={}", tls.cert.display()));
+            args.push(format!("--admin-tls-key={}", tls.key.display()));
+            if let Some(trusted) = &tls.trusted {
+                args.push(format!("--admin-tls-trusted={}", trusted.display()));
+            }
+        }
+        args
+    }
+}
+
+/// pem files used by the admin service, the same as `--admin-tls-*` options of pipy
+#[derive(Clone, Debug)]
+pub struct AdminTls {
+    /// server certificate
+    pub cert: PathBuf,
+    /// private key of the server certificate
+    pub key: PathBuf,
+    /// CA certificate to verify client certificates, clients without a certificate are rejected if set
+    pub trusted: Option<PathBuf>,
+}
+
+/// pipy running in repo mode in this process, needs the `libpipy` feature
+#[cfg(feature = "libpipy")]
 pub struct PipyRepo {
-    port: u16,
+    config: PipyConfig,
     is_started: Arc<atomic::AtomicBool>,
 }
+#[cfg(feature = "libpipy")]
 impl PipyRepo {
     pub fn new(port: u16) -> Self {
+        PipyRepo::with_config(PipyConfig::new(port))
+    }
+    pub fn with_config(config: PipyConfig) -> Self {
         PipyRepo {
-            port,
+            config,
             is_started: Arc::new(atomic::AtomicBool::new(false)),
         }
     }
+    pub fn config(&self) -> &PipyConfig {
+        &self.config
+    }
     pub fn start(&self) {
-        let port = self.port;
+        let config_args = self.config.args();
         let is_started = self.is_started.clone();
-        tracing::info!("start pipy with port: {}", port);
+        tracing::info!("start pipy with args: {:?}", config_args);
         thread::spawn(move || {
             let mut args: Vec<CString> = vec![];
             args.push(CString::new("pipy-rs").unwrap());
-            args.push(CString::new(format!("--admin-port={}", port)).unwrap());
+            for arg in config_args {
+                args.push(CString::new(arg).unwrap());
+            }
             let c_args: Vec<*const c_char> = args
                 .iter()
-                .map(|arg| <CString as Clone>::clone(&arg).into_raw() as *const c_char)
+                .map(|arg| <CString as Clone>::clone(arg).into_raw() as *const c_char)
                 .collect();
             is_started.store(true, atomic::Ordering::SeqCst);
             unsafe {
@@ -73,13 +136,14 @@ impl PipyRepo {
         }
     }
 }
+#[cfg(feature = "libpipy")]
 impl Drop for PipyRepo {
     fn drop(&mut self) {
         self.exit();
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "libpipy"))]
 mod tests {
     use util::init_logger;
 
@@ -151,4 +215,71 @@ mod tests {
         assert!(codebase_list_2.is_ok());
         assert!(codebase_list_2.unwrap().contains(&"test2".to_string()));
     }
+
+    #[tokio::test]
+    async fn test_pipy_repo_admin_tls() {
+        use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+        init_logger("info");
+        // generate a local CA, a server certificate and a client certificate signed by it
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = CertificateParams::new(vec!["127.0.0.1".to_string()])
+            .unwrap()
+            .signed_by(&server_key, &ca_cert, &ca_key)
+            .unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = CertificateParams::new(vec!["pipy-rs".to_string()])
+            .unwrap()
+            .signed_by(&client_key, &ca_cert, &ca_key)
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, pem: String| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, pem).unwrap();
+            path
+        };
+        let admin_tls = AdminTls {
+            cert: write("server.crt", server_cert.pem()),
+            key: write("server.key", server_key.serialize_pem()),
+            trusted: Some(write("ca.crt", ca_cert.pem())),
+        };
+
+        let port = 6443;
+        let repo = PipyRepo::with_config(PipyConfig::new(port).admin_tls(admin_tls));
+        repo.start();
+
+        let client = api_client::ApiClient::builder()
+            .host("127.0.0.1")
+            .port(port)
+            .https()
+            .root_certificate_pem(ca_cert.pem().as_bytes())
+            .client_identity_pem(
+                client_cert.pem().as_bytes(),
+                client_key.serialize_pem().as_bytes(),
+            )
+            .build()
+            .unwrap();
+        client.create_codebase("tls").await.unwrap();
+        let codebase_list = client.get_codebase_list().await.unwrap();
+        assert!(codebase_list.contains(&"tls".to_string()));
+
+        // a client without certificate is rejected
+        let anonymous = api_client::ApiClient::builder()
+            .host("127.0.0.1")
+            .port(port)
+            .https()
+            .root_certificate_pem(ca_cert.pem().as_bytes())
+            .build()
+            .unwrap();
+        assert!(anonymous.get_codebase_list().await.is_err());
+
+        // a plain http client can't talk to the tls port
+        let plain = api_client::ApiClient::new("127.0.0.1", port);
+        assert!(plain.get_codebase_list().await.is_err());
+    }
 }
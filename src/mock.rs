@@ -0,0 +1,625 @@
+//! In-memory pipy admin service for testing clients without libpipy, enabled with the `mock` feature
+//!
+//! serves the repo, repo-files and program endpoints of `pipy/src/admin-service.cpp`:
+//! file edits and erases stay pending until a PATCH with a new version publishes them,
+//! derived codebases inherit the published files of their base.
+//! codebase info is tagged with an `ETag` and answered with 304 for a matching `If-None-Match`.
+//! nothing is executed, starting a program only records it
+//!
+//! request bodies are parsed by the mock's own types, so a client bug in serializing them isn't hidden.
+//! with `cargo test --no-default-features --features mock` the CMake build of libpipy is skipped,
+//! so the tests need neither cmake nor the pipy submodule
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Incoming,
+    header::{ETAG, IF_NONE_MATCH},
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::api_client::{
+    api::{CodebaseVersion, Program},
+    ApiClient,
+};
+
+/// the main.js of a new codebase
+pub const DEFAULT_MAIN_JS: &str = "pipy()\n";
+
+/// a mock admin service on a random local port, stopped when dropped
+///
+/// must be started inside a tokio runtime, connections are served by spawned tasks
+pub struct MockRepoServer {
+    addr: SocketAddr,
+    repo: Arc<Mutex<Repo>>,
+    task: JoinHandle<()>,
+}
+
+impl MockRepoServer {
+    pub async fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let repo = Arc::new(Mutex::new(Repo::default()));
+        let task = tokio::spawn(serve(listener, repo.clone()));
+        Ok(MockRepoServer { addr, repo, task })
+    }
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+    /// base url like `http://127.0.0.1:PORT/`
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+    /// a client of this server
+    pub fn client(&self) -> ApiClient {
+        ApiClient::new("127.0.0.1", self.port())
+    }
+    /// the running program, like GET /api/v1/program
+    pub fn program(&self) -> Option<Program> {
+        let repo = self.repo.lock().unwrap();
+        repo.program.as_ref().map(|program| Program {
+            path: program.path.clone(),
+            argv: program.argv.clone(),
+        })
+    }
+}
+
+impl Drop for MockRepoServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(listener: TcpListener, repo: Arc<Mutex<Repo>>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!("mock repo accept error: {}", e);
+                continue;
+            }
+        };
+        let repo = repo.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| {
+                let repo = repo.clone();
+                async move { Ok::<_, Infallible>(handle(&repo, req).await) }
+            });
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("mock repo connection error: {}", e);
+            }
+        });
+    }
+}
+
+type MockResponse = Response<Full<Bytes>>;
+
+fn reply(status: StatusCode, body: impl Into<Bytes>) -> MockResponse {
+    let mut resp = Response::new(Full::new(body.into()));
+    *resp.status_mut() = status;
+    resp
+}
+
+fn not_found(what: &str) -> MockResponse {
+    reply(StatusCode::NOT_FOUND, format!("{} not found", what))
+}
+
+async fn handle(repo: &Mutex<Repo>, req: Request<Incoming>) -> MockResponse {
+    let method = req.method().clone();
+    let if_none_match = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let path = match percent_encoding::percent_decode_str(req.uri().path()).decode_utf8() {
+        Ok(path) => path.into_owned(),
+        Err(_) => return reply(StatusCode::BAD_REQUEST, "invalid path"),
+    };
+    let body = match req.into_body().collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => return reply(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    tracing::debug!("mock repo: {} {}", method, path);
+
+    let mut repo = repo.lock().unwrap();
+    let Some(path) = path.strip_prefix("/api/v1/") else {
+        return not_found(&path);
+    };
+    if path == "repo" || path == "repo/" {
+        return match method {
+            Method::GET => repo.list(),
+            _ => reply(StatusCode::METHOD_NOT_ALLOWED, ""),
+        };
+    }
+    if let Some(name) = path.strip_prefix("repo/") {
+        let name = name.trim_end_matches('/');
+        return match method {
+            Method::GET => repo.get_codebase(name, if_none_match.as_deref()),
+            Method::POST => repo.create_codebase(name, &body),
+            Method::PATCH => repo.patch_codebase(name, &body),
+            Method::DELETE => repo.delete_codebase(name),
+            _ => reply(StatusCode::METHOD_NOT_ALLOWED, ""),
+        };
+    }
+    if let Some(path) = path.strip_prefix("repo-files/") {
+        let Some((name, file)) = repo.split_file_path(path) else {
+            return not_found(path);
+        };
+        return match method {
+            Method::GET => repo.get_file(&name, &file),
+            Method::POST => repo.update_file(&name, &file, body),
+            Method::DELETE => repo.delete_file(&name, &file),
+            _ => reply(StatusCode::METHOD_NOT_ALLOWED, ""),
+        };
+    }
+    if path == "program" {
+        return match method {
+            Method::GET => repo.get_program(),
+            Method::POST => repo.start_program(&body),
+            Method::DELETE => {
+                repo.program = None;
+                reply(StatusCode::OK, "")
+            }
+            _ => reply(StatusCode::METHOD_NOT_ALLOWED, ""),
+        };
+    }
+    not_found(path)
+}
+
+#[derive(Default)]
+struct Repo {
+    codebases: BTreeMap<String, MockCodebase>,
+    program: Option<MockProgram>,
+}
+
+/// file names start with '/', like in pipy
+struct MockCodebase {
+    version: CodebaseVersion,
+    main: String,
+    base: Option<String>,
+    published: BTreeMap<String, Bytes>,
+    edited: BTreeMap<String, Bytes>,
+    erased: BTreeSet<String>,
+}
+
+/// the body of POST /api/v1/repo/[CODEBASE]
+#[derive(Deserialize, Default)]
+struct CreateCodebase {
+    base: Option<String>,
+    main: Option<String>,
+    version: Option<CodebaseVersion>,
+}
+
+/// the body of PATCH /api/v1/repo/[CODEBASE], absent fields are left untouched
+#[derive(Deserialize, Default)]
+struct PatchCodebase {
+    main: Option<String>,
+    base: Option<String>,
+    version: Option<CodebaseVersion>,
+}
+
+/// the body of POST /api/v1/program, a plain codebase path or `{ path, argv }`
+#[derive(Serialize, Deserialize)]
+struct MockProgram {
+    path: String,
+    #[serde(default)]
+    argv: Vec<String>,
+}
+
+impl MockProgram {
+    fn parse(body: &[u8]) -> Result<Self, String> {
+        let body = String::from_utf8_lossy(body);
+        let body = body.trim();
+        if body.is_empty() {
+            return Err("missing program path".to_string());
+        }
+        let mut program = if body.starts_with('{') {
+            serde_json::from_str(body).map_err(|e| e.to_string())?
+        } else {
+            MockProgram {
+                path: body.to_string(),
+                argv: vec![],
+            }
+        };
+        program.path = format!("/{}", codebase_key(&program.path));
+        if program.argv.is_empty() {
+            program.argv.push(program.path.clone());
+        }
+        Ok(program)
+    }
+}
+
+fn file_key(file: &str) -> String {
+    format!("/{}", file.trim_start_matches('/'))
+}
+
+fn codebase_key(name: &str) -> &str {
+    name.trim_start_matches('/')
+}
+
+impl Repo {
+    fn list(&self) -> MockResponse {
+        let list: Vec<String> = self
+            .codebases
+            .keys()
+            .map(|name| format!("/{}", name))
+            .collect();
+        reply(StatusCode::OK, list.join("\n"))
+    }
+
+    /// published files of the codebase including inherited ones, the closest definition wins
+    fn visible_files(&self, name: &str) -> BTreeMap<String, Bytes> {
+        let Some(codebase) = self.codebases.get(name) else {
+            return BTreeMap::new();
+        };
+        let mut files = match &codebase.base {
+            Some(base) => self.visible_files(base),
+            None => BTreeMap::new(),
+        };
+        files.extend(codebase.published.clone());
+        files
+    }
+
+    fn get_codebase(&self, name: &str, if_none_match: Option<&str>) -> MockResponse {
+        let name = codebase_key(name);
+        let Some(codebase) = self.codebases.get(name) else {
+            return not_found(name);
+        };
+        let files: BTreeSet<&String> = codebase
+            .published
+            .keys()
+            .chain(codebase.edited.keys())
+            .collect();
+        let base_files: Vec<String> = match &codebase.base {
+            Some(base) => self.visible_files(base).into_keys().collect(),
+            None => vec![],
+        };
+        let derived: Vec<String> = self
+            .codebases
+            .iter()
+            .filter(|(_, c)| c.base.as_deref() == Some(name))
+            .map(|(n, _)| format!("/{}", n))
+            .collect();
+        let info = serde_json::json!({
+            "version": codebase.version,
+            "path": format!("/{}", name),
+            "main": codebase.main,
+            "files": files,
+            "editFiles": codebase.edited.keys().collect::<Vec<_>>(),
+            "erasedFiles": codebase.erased,
+            "baseFiles": base_files,
+            "derived": derived,
+            "base": codebase.base.as_ref().map(|b| format!("/{}", b)),
+            "instances": {},
+        })
+        .to_string();
+        let mut hasher = DefaultHasher::new();
+        info.hash(&mut hasher);
+        let etag = format!("\"{:x}\"", hasher.finish());
+        let mut resp = if if_none_match == Some(etag.as_str()) {
+            reply(StatusCode::NOT_MODIFIED, "")
+        } else {
+            reply(StatusCode::OK, info)
+        };
+        resp.headers_mut()
+            .insert(ETAG, etag.parse().expect("etag is a valid header"));
+        resp
+    }
+
+    fn create_codebase(&mut self, name: &str, body: &[u8]) -> MockResponse {
+        let name = codebase_key(name);
+        if name.is_empty() {
+            return reply(StatusCode::BAD_REQUEST, "missing codebase name");
+        }
+        if self.codebases.contains_key(name) {
+            return reply(StatusCode::CONFLICT, "exists");
+        }
+        let options: CreateCodebase = if body.is_empty() {
+            CreateCodebase::default()
+        } else {
+            match serde_json::from_slice(body) {
+                Ok(options) => options,
+                Err(e) => return reply(StatusCode::BAD_REQUEST, e.to_string()),
+            }
+        };
+        let base = options.base.as_deref().map(|b| codebase_key(b).to_string());
+        let mut published = BTreeMap::new();
+        let main = match &base {
+            Some(base) => match self.codebases.get(base) {
+                Some(base) => base.main.clone(),
+                None => return not_found(base),
+            },
+            None => {
+                published.insert("/main.js".to_string(), Bytes::from(DEFAULT_MAIN_JS));
+                "/main.js".to_string()
+            }
+        };
+        self.codebases.insert(
+            name.to_string(),
+            MockCodebase {
+                version: options.version.unwrap_or_else(|| CodebaseVersion::new("0")),
+                main: options.main.map_or(main, |m| file_key(&m)),
+                base,
+                published,
+                edited: BTreeMap::new(),
+                erased: BTreeSet::new(),
+            },
+        );
+        reply(StatusCode::CREATED, "")
+    }
+
+    fn patch_codebase(&mut self, name: &str, body: &[u8]) -> MockResponse {
+        let name = codebase_key(name);
+        if !self.codebases.contains_key(name) {
+            return not_found(name);
+        }
+        let update: PatchCodebase = match serde_json::from_slice(body) {
+            Ok(update) => update,
+            Err(e) => return reply(StatusCode::BAD_REQUEST, e.to_string()),
+        };
+        if let Some(base) = &update.base {
+            let base = codebase_key(base);
+            if base == name || !self.codebases.contains_key(base) {
+                return reply(StatusCode::BAD_REQUEST, format!("invalid base {}", base));
+            }
+        }
+        if let Some(main) = &update.main {
+            let main = file_key(main);
+            let codebase = &self.codebases[name];
+            let exists = codebase.published.contains_key(&main)
+                || codebase.edited.contains_key(&main)
+                || codebase
+                    .base
+                    .as_ref()
+                    .is_some_and(|base| self.visible_files(base).contains_key(&main));
+            if !exists {
+                return reply(StatusCode::BAD_REQUEST, format!("{} not found", main));
+            }
+        }
+
+        let codebase = self.codebases.get_mut(name).expect("checked above");
+        if let Some(base) = update.base {
+            codebase.base = Some(codebase_key(&base).to_string());
+        }
+        if let Some(main) = update.main {
+            codebase.main = file_key(&main);
+        }
+        if let Some(version) = update.version {
+            // a new version publishes the pending edits and erases
+            let edited = std::mem::take(&mut codebase.edited);
+            codebase.published.extend(edited);
+            for file in std::mem::take(&mut codebase.erased) {
+                codebase.published.remove(&file);
+            }
+            codebase.version = version;
+        }
+        reply(StatusCode::OK, "")
+    }
+
+    fn delete_codebase(&mut self, name: &str) -> MockResponse {
+        let name = codebase_key(name);
+        if !self.codebases.contains_key(name) {
+            return not_found(name);
+        }
+        if self
+            .codebases
+            .values()
+            .any(|c| c.base.as_deref() == Some(name))
+        {
+            return reply(
+                StatusCode::BAD_REQUEST,
+                format!("{} has derived codebases", name),
+            );
+        }
+        self.codebases.remove(name);
+        reply(StatusCode::OK, "")
+    }
+
+    /// split `CODEBASE/FILE`, codebase names may contain '/' so the longest existing one is taken
+    fn split_file_path(&self, path: &str) -> Option<(String, String)> {
+        path.rmatch_indices('/')
+            .map(|(i, _)| (&path[..i], &path[i..]))
+            .find(|(name, file)| self.codebases.contains_key(*name) && file.len() > 1)
+            .map(|(name, file)| (name.to_string(), file.to_string()))
+    }
+
+    fn get_file(&self, name: &str, file: &str) -> MockResponse {
+        let codebase = &self.codebases[name];
+        if codebase.erased.contains(file) {
+            return not_found(file);
+        }
+        let data = match codebase
+            .edited
+            .get(file)
+            .or_else(|| codebase.published.get(file))
+        {
+            Some(data) => data.clone(),
+            None => match codebase
+                .base
+                .as_ref()
+                .and_then(|base| self.visible_files(base).remove(file))
+            {
+                Some(data) => data,
+                None => return not_found(file),
+            },
+        };
+        reply(StatusCode::OK, data)
+    }
+
+    fn update_file(&mut self, name: &str, file: &str, data: Bytes) -> MockResponse {
+        let codebase = self
+            .codebases
+            .get_mut(name)
+            .expect("split by existing codebase");
+        codebase.erased.remove(file);
+        codebase.edited.insert(file.to_string(), data);
+        reply(StatusCode::CREATED, "")
+    }
+
+    fn delete_file(&mut self, name: &str, file: &str) -> MockResponse {
+        let codebase = self
+            .codebases
+            .get_mut(name)
+            .expect("split by existing codebase");
+        let edited = codebase.edited.remove(file).is_some();
+        if codebase.published.contains_key(file) && !codebase.erased.contains(file) {
+            codebase.erased.insert(file.to_string());
+        } else if !edited {
+            return not_found(file);
+        }
+        reply(StatusCode::OK, "")
+    }
+
+    fn get_program(&self) -> MockResponse {
+        let body = match &self.program {
+            None => String::new(),
+            Some(program) if program.argv.len() <= 1 => program.path.clone(),
+            Some(program) => serde_json::to_string(program).expect("program is serializable"),
+        };
+        reply(StatusCode::OK, body)
+    }
+
+    fn start_program(&mut self, body: &[u8]) -> MockResponse {
+        let program = match MockProgram::parse(body) {
+            Ok(program) => program,
+            Err(e) => return reply(StatusCode::BAD_REQUEST, e),
+        };
+        let name = codebase_key(&program.path);
+        if !self.codebases.contains_key(name) {
+            return not_found(name);
+        }
+        self.program = Some(program);
+        reply(StatusCode::CREATED, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api_client::api::{ApiError, ResourceKind};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_codebase() {
+        let server = MockRepoServer::start().await.unwrap();
+        let client = server.client();
+
+        assert!(client.get_codebase_list().await.unwrap().is_empty());
+        assert!(client
+            .get_codebase("hello")
+            .await
+            .unwrap_err()
+            .is_not_found());
+        client.create_codebase("hello").await.unwrap();
+        assert!(matches!(
+            client.create_codebase("hello").await,
+            Err(ApiError::Conflict { .. })
+        ));
+        assert_eq!(client.get_codebase_list().await.unwrap(), vec!["hello"]);
+        assert_eq!(
+            client.get_file("hello", "main.js").await.unwrap(),
+            DEFAULT_MAIN_JS.as_bytes()
+        );
+
+        // edits are visible but pending until published
+        client
+            .update_file("hello", "util.js", b"export default {}".to_vec())
+            .await
+            .unwrap();
+        client.delete_file("hello", "main.js").await.unwrap();
+        let codebase = client.get_codebase("hello").await.unwrap();
+        assert_eq!(codebase.edit_files, vec!["/util.js"]);
+        assert_eq!(codebase.erased_files, vec!["/main.js"]);
+        // still listed until published, but no longer a file of the codebase
+        assert!(codebase.files.contains(&"/main.js".to_string()));
+        assert!(!codebase.contains_file("main.js"));
+        assert!(client
+            .get_file("hello", "main.js")
+            .await
+            .unwrap_err()
+            .is_not_found());
+
+        let version = client.publish_changes("hello").await.unwrap();
+        let codebase = client.get_codebase("hello").await.unwrap();
+        assert_eq!(codebase.version, version);
+        assert_eq!(codebase.files, vec!["/util.js"]);
+        assert!(codebase.edit_files.is_empty() && codebase.erased_files.is_empty());
+        assert!(matches!(
+            client.delete_file("hello", "main.js").await,
+            Err(ApiError::NotFound {
+                kind: ResourceKind::File,
+                ..
+            })
+        ));
+        assert!(matches!(
+            client.update_file("missing", "main.js", vec![]).await,
+            Err(ApiError::NotFound {
+                kind: ResourceKind::Codebase,
+                ..
+            })
+        ));
+
+        // derived codebases see the published files of their base
+        client
+            .create_derived_codebase("hello/derived", "hello")
+            .await
+            .unwrap();
+        let derived = client.get_codebase("hello/derived").await.unwrap();
+        assert_eq!(derived.base.as_deref(), Some("/hello"));
+        assert_eq!(derived.base_files, vec!["/util.js"]);
+        assert_eq!(
+            client.get_file("hello/derived", "util.js").await.unwrap(),
+            b"export default {}"
+        );
+        let tree = client.get_derivation_tree("hello").await.unwrap();
+        assert_eq!(tree.derived[0].name, "hello/derived");
+        assert!(matches!(
+            client.delete_codebase("hello").await,
+            Err(ApiError::BadRequest { .. })
+        ));
+        client.delete_codebase("hello/derived").await.unwrap();
+        client.delete_codebase("hello").await.unwrap();
+        assert!(client.get_codebase_list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mock_program() {
+        let server = MockRepoServer::start().await.unwrap();
+        let client = server.client();
+
+        assert!(matches!(
+            client.start_repo("hello").await,
+            Err(ApiError::NotFound { .. })
+        ));
+        client.create_codebase("hello").await.unwrap();
+        client.start_repo("hello").await.unwrap();
+        assert_eq!(
+            client.current_repo().await.unwrap().as_deref(),
+            Some("hello")
+        );
+        client
+            .start_program("hello", &["--port", "8080"])
+            .await
+            .unwrap();
+        let program = client.current_program().await.unwrap().unwrap();
+        assert_eq!(program.args(), ["--port", "8080"]);
+        assert_eq!(server.program(), Some(program));
+        client.stop_repo().await.unwrap();
+        assert!(client.current_program().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,229 @@
+//! Native pipelines written in Rust
+//!
+//! mirrors the event model of pipy's native module interface (NMI): a stream is a sequence of
+//! [`Event`]s, a message is `MessageStart`, any number of `Data` and `MessageEnd`.
+//! heads and tails are the objects scripts see, such as `{ method, path, headers }` of a request,
+//! so they are kept as json values, as are the context variables of a stream in [`Context`].
+//! only the Rust side lives here, registering a pipeline with pipy needs the NMI bindings
+use std::{fmt, future::Future};
+
+use bytes::{Bytes, BytesMut};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    FutureExt, StreamExt,
+};
+use serde_json::Value;
+
+pub mod context;
+pub mod http;
+
+pub use context::{Context, ContextVar};
+
+/// an event flowing through a pipeline
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// start of a message with its head, `Value::Null` if it has none
+    MessageStart(Value),
+    Data(Bytes),
+    /// end of a message with its tail, such as the trailers of http
+    MessageEnd(Value),
+    /// end of the stream, with the error if it ended abnormally
+    StreamEnd(Option<String>),
+}
+impl Event {
+    pub fn data(data: impl Into<Bytes>) -> Self {
+        Event::Data(data.into())
+    }
+}
+
+/// a whole message, see [`MessageReader`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub head: Value,
+    pub body: Bytes,
+    pub tail: Value,
+}
+impl Message {
+    pub fn new(head: Value, body: impl Into<Bytes>) -> Self {
+        Message {
+            head,
+            body: body.into(),
+            tail: Value::Null,
+        }
+    }
+    /// the events of the message, `Data` is left out for an empty body
+    pub fn into_events(self) -> Vec<Event> {
+        let mut events = vec![Event::MessageStart(self.head)];
+        if !self.body.is_empty() {
+            events.push(Event::Data(self.body));
+        }
+        events.push(Event::MessageEnd(self.tail));
+        events
+    }
+}
+
+type Scheduled = Box<dyn FnOnce(&mut Output) + Send>;
+
+/// events emitted by a pipeline while processing an input event
+pub struct Output {
+    events: Vec<Event>,
+    sender: UnboundedSender<Scheduled>,
+    scheduled: UnboundedReceiver<Scheduled>,
+}
+impl Default for Output {
+    fn default() -> Self {
+        let (sender, scheduled) = unbounded();
+        Output {
+            events: vec![],
+            sender,
+            scheduled,
+        }
+    }
+}
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Output")
+            .field("events", &self.events)
+            .finish_non_exhaustive()
+    }
+}
+impl Output {
+    pub fn emit(&mut self, event: Event) {
+        self.events.push(event);
+    }
+    pub fn emit_message(&mut self, message: Message) {
+        self.events.extend(message.into_events());
+    }
+    pub fn take(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+    /// a handle to emit from other threads, see [`OutputHandle`]
+    pub fn handle(&self) -> OutputHandle {
+        OutputHandle {
+            sender: self.sender.clone(),
+        }
+    }
+    /// run the work queued through handles so far, in order, returns how much was run
+    pub fn run_scheduled(&mut self) -> usize {
+        let mut count = 0;
+        while let Some(Some(task)) = self.scheduled.next().now_or_never() {
+            task(self);
+            count += 1;
+        }
+        count
+    }
+    /// wait until work is queued through a handle, then run it with anything queued after it
+    pub async fn next_scheduled(&mut self) {
+        // never ends, the output keeps a sender of its own
+        if let Some(task) = self.scheduled.next().await {
+            task(self);
+        }
+        self.run_scheduled();
+    }
+}
+
+/// a `Send` handle to queue work onto the owner of an [`Output`], like NMI's `pipy_schedule`
+/// runs work on the pipy thread of a stream. async work such as a tokio task or another thread
+/// finishes a pending stream with it, the queued work runs when the owner calls [`Output::run_scheduled`]
+#[derive(Clone)]
+pub struct OutputHandle {
+    sender: UnboundedSender<Scheduled>,
+}
+impl OutputHandle {
+    /// queue an event to emit, false if the output is gone
+    pub fn emit(&self, event: Event) -> bool {
+        self.schedule(move |output| output.emit(event))
+    }
+    pub fn emit_message(&self, message: Message) -> bool {
+        self.schedule(move |output| output.emit_message(message))
+    }
+    /// queue a closure to run with the output, false if the output is gone
+    pub fn schedule(&self, f: impl FnOnce(&mut Output) + Send + 'static) -> bool {
+        self.sender.unbounded_send(Box::new(f)).is_ok()
+    }
+}
+
+/// a pipeline implemented in Rust, one instance per stream
+pub trait NativePipeline: Send {
+    /// handle an input event of the stream, emitting any output to `output`.
+    /// `context` holds the context variables of the stream, shared with the scripts
+    fn process(
+        &mut self,
+        event: Event,
+        context: &mut Context,
+        output: &mut Output,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+/// assembles messages from events, data outside of a message is dropped
+#[derive(Debug, Default)]
+pub struct MessageReader {
+    head: Option<Value>,
+    body: BytesMut,
+}
+impl MessageReader {
+    /// feed an event, the message is returned on its `MessageEnd`
+    pub fn read(&mut self, event: &Event) -> Option<Message> {
+        match event {
+            Event::MessageStart(head) => {
+                self.head = Some(head.clone());
+                self.body.clear();
+            }
+            Event::Data(data) if self.head.is_some() => self.body.extend_from_slice(data),
+            Event::MessageEnd(tail) => {
+                let head = self.head.take()?;
+                return Some(Message {
+                    head,
+                    body: self.body.split().freeze(),
+                    tail: tail.clone(),
+                });
+            }
+            Event::Data(_) | Event::StreamEnd(_) => {}
+        }
+        None
+    }
+    /// whether a message is started but not ended
+    pub fn is_reading(&self) -> bool {
+        self.head.is_some()
+    }
+}
+
+/// the whole messages in `events`
+pub fn read_messages<'a>(events: impl IntoIterator<Item = &'a Event>) -> Vec<Message> {
+    let mut reader = MessageReader::default();
+    events.into_iter().filter_map(|e| reader.read(e)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_read_messages() {
+        let events = vec![
+            Event::data("dropped"),
+            Event::MessageStart(json!({ "path": "/" })),
+            Event::data("hello "),
+            Event::data("world"),
+            Event::MessageEnd(Value::Null),
+            Event::MessageStart(Value::Null),
+            Event::MessageEnd(json!({ "trailer": "x" })),
+            Event::StreamEnd(None),
+        ];
+        let messages = read_messages(&events);
+        assert_eq!(
+            messages,
+            vec![
+                Message::new(json!({ "path": "/" }), "hello world"),
+                Message {
+                    head: Value::Null,
+                    body: Bytes::new(),
+                    tail: json!({ "trailer": "x" }),
+                },
+            ]
+        );
+        assert_eq!(messages[1].clone().into_events(), events[5..7]);
+    }
+}
@@ -0,0 +1,109 @@
+//! Context variables shared by the scripts and native pipelines of a stream
+//!
+//! NMI modules declare variables with a name and a default, scripts then read and write them
+//! like any other context variable, e.g. a tenant id set by the routing script and read by a
+//! Rust filter. values are kept as json, the way scripts see them, and typed by the [`ContextVar`]
+use std::collections::BTreeMap;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// a context variable declared from Rust, unset or mistyped values read as its default
+#[derive(Debug, Clone)]
+pub struct ContextVar<T> {
+    name: String,
+    default: T,
+}
+
+impl<T> ContextVar<T> {
+    pub fn new(name: &str, default: T) -> Self {
+        ContextVar {
+            name: name.to_string(),
+            default,
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn default_value(&self) -> &T {
+        &self.default
+    }
+}
+
+/// the context variables of one stream, see [`super::NativePipeline::process`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Context {
+    values: BTreeMap<String, Value>,
+}
+
+impl Context {
+    /// the value of `var`, its default if not set or not a `T`
+    pub fn get<T: Clone + DeserializeOwned>(&self, var: &ContextVar<T>) -> T {
+        match self.values.get(&var.name) {
+            Some(value) => serde_json::from_value(value.clone()).unwrap_or_else(|e| {
+                tracing::debug!("context variable {}: {}", var.name, e);
+                var.default.clone()
+            }),
+            None => var.default.clone(),
+        }
+    }
+    pub fn set<T: Serialize>(&mut self, var: &ContextVar<T>, value: T) -> serde_json::Result<()> {
+        self.values
+            .insert(var.name.clone(), serde_json::to_value(value)?);
+        Ok(())
+    }
+    /// back to the default of `var`
+    pub fn reset<T>(&mut self, var: &ContextVar<T>) {
+        self.values.remove(&var.name);
+    }
+    /// the value as scripts see it, `None` if not set
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+    /// set a value the way a script does, without a declared type
+    pub fn set_value(&mut self, name: &str, value: Value) {
+        self.values.insert(name.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Auth {
+        user: String,
+        roles: Vec<String>,
+    }
+
+    #[test]
+    fn test_context_vars() {
+        let tenant = ContextVar::new("tenant", "default".to_string());
+        let auth = ContextVar::new("auth", None::<Auth>);
+        let mut context = Context::default();
+        assert_eq!(context.get(&tenant), "default");
+        assert_eq!(context.get(&auth), None);
+
+        // set by a script
+        context.set_value("tenant", json!("acme"));
+        assert_eq!(context.get(&tenant), "acme");
+        context.set_value("tenant", json!(42));
+        assert_eq!(context.get(&tenant), "default");
+
+        let user = Auth {
+            user: "alice".to_string(),
+            roles: vec!["admin".to_string()],
+        };
+        context.set(&auth, Some(user.clone())).unwrap();
+        assert_eq!(context.get(&auth), Some(user));
+        assert_eq!(
+            context.value("auth"),
+            Some(&json!({ "user": "alice", "roles": ["admin"] }))
+        );
+        context.reset(&auth);
+        assert_eq!(context.value("auth"), None);
+    }
+}
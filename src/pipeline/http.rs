@@ -0,0 +1,245 @@
+//! Serve http requests of a pipeline with Rust handlers
+//!
+//! after `demuxHTTP()` every message is a request with a head like `{ protocol, method, path, headers }`,
+//! and the reply is a message with a head like `{ status, headers }`.
+//! nothing in this crate registers a pipeline with pipy yet, so until there are NMI bindings an
+//! [`HttpPipeline`] is only driven in-process, e.g. with [`crate::testing::PipelineHarness`]
+use std::future::Future;
+
+use ::http::{HeaderValue, Request, Response, StatusCode, Version};
+use bytes::Bytes;
+use serde_json::{json, Map, Value};
+
+use super::{Context, Event, Message, MessageReader, NativePipeline, Output};
+
+/// an async handler of http requests, implemented for closures returning a future
+pub trait HttpHandler: Send {
+    fn call(&mut self, request: Request<Bytes>) -> impl Future<Output = Response<Bytes>> + Send;
+}
+
+impl<F, Fut> HttpHandler for F
+where
+    F: FnMut(Request<Bytes>) -> Fut + Send,
+    Fut: Future<Output = Response<Bytes>> + Send,
+{
+    fn call(&mut self, request: Request<Bytes>) -> impl Future<Output = Response<Bytes>> + Send {
+        self(request)
+    }
+}
+
+/// a `tower::Service` as [`HttpHandler`], an error of the service is answered with 500
+#[cfg(feature = "tower")]
+pub struct ServiceHandler<S>(pub S);
+
+#[cfg(feature = "tower")]
+impl<S> HttpHandler for ServiceHandler<S>
+where
+    S: tower_service::Service<Request<Bytes>, Response = Response<Bytes>> + Send,
+    S::Future: Send,
+    S::Error: std::fmt::Display,
+{
+    async fn call(&mut self, request: Request<Bytes>) -> Response<Bytes> {
+        if let Err(e) = futures::future::poll_fn(|cx| self.0.poll_ready(cx)).await {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, e);
+        }
+        match self.0.call(request).await {
+            Ok(response) => response,
+            Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+        }
+    }
+}
+
+/// a pipeline answering each request message with the response of `handler`
+pub struct HttpPipeline<H> {
+    handler: H,
+    reader: MessageReader,
+}
+
+impl<H: HttpHandler> HttpPipeline<H> {
+    pub fn new(handler: H) -> Self {
+        HttpPipeline {
+            handler,
+            reader: MessageReader::default(),
+        }
+    }
+}
+
+impl<H: HttpHandler> NativePipeline for HttpPipeline<H> {
+    async fn process(&mut self, event: Event, _context: &mut Context, output: &mut Output) {
+        if let Event::StreamEnd(_) = event {
+            output.emit(event);
+            return;
+        }
+        let Some(message) = self.reader.read(&event) else {
+            return;
+        };
+        let response = match to_request(message) {
+            Ok(request) => self.handler.call(request).await,
+            Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+        };
+        output.emit_message(to_message(response));
+    }
+}
+
+/// the request of a message from `demuxHTTP()`
+pub fn to_request(message: Message) -> Result<Request<Bytes>, ::http::Error> {
+    let head = &message.head;
+    let field = |name: &str| head.get(name).and_then(Value::as_str);
+    let version = match field("protocol") {
+        Some("HTTP/1.0") => Version::HTTP_10,
+        Some("HTTP/2") | Some("HTTP/2.0") => Version::HTTP_2,
+        _ => Version::HTTP_11,
+    };
+    let mut builder = Request::builder()
+        .method(field("method").unwrap_or("GET"))
+        .uri(field("path").unwrap_or("/"))
+        .version(version);
+    if let Some(headers) = head.get("headers").and_then(Value::as_object) {
+        for (name, value) in headers {
+            // repeated headers such as set-cookie come as an array
+            let values = match value {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            for value in values {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    value => value.to_string(),
+                };
+                builder = builder.header(name.as_str(), value);
+            }
+        }
+    }
+    builder.body(message.body)
+}
+
+/// the message of a response, for the `demuxHTTP()` that sends it to the client
+pub fn to_message(response: Response<Bytes>) -> Message {
+    let (parts, body) = response.into_parts();
+    let mut headers = Map::new();
+    for name in parts.headers.keys() {
+        let mut values: Vec<Value> = parts
+            .headers
+            .get_all(name)
+            .iter()
+            .map(|v| Value::String(header_str(v)))
+            .collect();
+        let value = if values.len() == 1 {
+            values.remove(0)
+        } else {
+            Value::Array(values)
+        };
+        headers.insert(name.as_str().to_string(), value);
+    }
+    Message::new(
+        json!({ "status": parts.status.as_u16(), "headers": headers }),
+        body,
+    )
+}
+
+fn header_str(value: &HeaderValue) -> String {
+    String::from_utf8_lossy(value.as_bytes()).to_string()
+}
+
+fn error_response(status: StatusCode, error: impl std::fmt::Display) -> Response<Bytes> {
+    let mut response = Response::new(Bytes::from(error.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::PipelineHarness;
+
+    use super::*;
+
+    #[test]
+    fn test_to_request() {
+        let message = Message::new(
+            json!({
+                "protocol": "HTTP/1.0",
+                "method": "POST",
+                "path": "/users?id=1",
+                "headers": { "host": "example.com", "cookie": ["a=1", "b=2"], "x-n": 1 },
+            }),
+            "body",
+        );
+        let request = to_request(message).unwrap();
+        assert_eq!(request.method(), "POST");
+        assert_eq!(request.uri().path(), "/users");
+        assert_eq!(request.uri().query(), Some("id=1"));
+        assert_eq!(request.version(), Version::HTTP_10);
+        assert_eq!(request.headers()["host"], "example.com");
+        assert_eq!(request.headers().get_all("cookie").iter().count(), 2);
+        assert_eq!(request.headers()["x-n"], "1");
+        assert_eq!(request.body(), "body");
+
+        let invalid = Message::new(json!({ "method": "BAD METHOD" }), "");
+        assert!(to_request(invalid).is_err());
+    }
+
+    #[test]
+    fn test_to_message() {
+        let response = Response::builder()
+            .status(201)
+            .header("content-type", "text/plain")
+            .header("set-cookie", "a=1")
+            .header("set-cookie", "b=2")
+            .body(Bytes::from("created"))
+            .unwrap();
+        assert_eq!(
+            to_message(response),
+            Message::new(
+                json!({
+                    "status": 201,
+                    "headers": { "content-type": "text/plain", "set-cookie": ["a=1", "b=2"] },
+                }),
+                "created"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_pipeline() {
+        let mut harness =
+            PipelineHarness::new(HttpPipeline::new(|request: Request<Bytes>| async move {
+                let body = format!("{} {}", request.method(), request.uri());
+                Response::new(Bytes::from(body))
+            }));
+        let request = Message::new(json!({ "method": "GET", "path": "/hello" }), "");
+        let responses = harness.send_message(request).await;
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].head["status"], 200);
+        assert_eq!(responses[0].body, "GET /hello");
+
+        let invalid = Message::new(json!({ "method": "BAD METHOD" }), "");
+        let responses = harness.send_message(invalid).await;
+        assert_eq!(responses[0].head["status"], 400);
+        assert_eq!(harness.end(None).await, vec![Event::StreamEnd(None)]);
+    }
+
+    #[cfg(feature = "tower")]
+    #[tokio::test]
+    async fn test_service_handler() {
+        use std::task::{Context, Poll};
+
+        struct Fail;
+        impl tower_service::Service<Request<Bytes>> for Fail {
+            type Response = Response<Bytes>;
+            type Error = String;
+            type Future = std::future::Ready<Result<Response<Bytes>, String>>;
+            fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), String>> {
+                Poll::Ready(Ok(()))
+            }
+            fn call(&mut self, request: Request<Bytes>) -> Self::Future {
+                std::future::ready(Err(format!("no route to {}", request.uri())))
+            }
+        }
+        let mut harness = PipelineHarness::new(HttpPipeline::new(ServiceHandler(Fail)));
+        let responses = harness
+            .send_message(Message::new(json!({ "path": "/x" }), ""))
+            .await;
+        assert_eq!(responses[0].head["status"], 500);
+        assert_eq!(responses[0].body, "no route to /x");
+    }
+}
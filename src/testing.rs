@@ -0,0 +1,203 @@
+//! Drive a [`NativePipeline`] in-process, to unit-test filters without running pipy
+use crate::pipeline::{
+    read_messages, Context, Event, Message, NativePipeline, Output, OutputHandle,
+};
+
+/// feeds scripted events to a pipeline and keeps what it emits
+pub struct PipelineHarness<P> {
+    pipeline: P,
+    context: Context,
+    output: Output,
+    emitted: Vec<Event>,
+}
+
+impl<P: NativePipeline> PipelineHarness<P> {
+    pub fn new(pipeline: P) -> Self {
+        PipelineHarness {
+            pipeline,
+            context: Context::default(),
+            output: Output::default(),
+            emitted: vec![],
+        }
+    }
+    /// process one input event, returns the events emitted for it
+    pub async fn send(&mut self, event: Event) -> Vec<Event> {
+        self.pipeline
+            .process(event, &mut self.context, &mut self.output)
+            .await;
+        self.output.run_scheduled();
+        let events = self.output.take();
+        self.emitted.extend(events.iter().cloned());
+        events
+    }
+    /// process the events in order, returns the events emitted for all of them
+    pub async fn send_all(&mut self, events: impl IntoIterator<Item = Event>) -> Vec<Event> {
+        let mut emitted = vec![];
+        for event in events {
+            emitted.extend(self.send(event).await);
+        }
+        emitted
+    }
+    /// send a whole message, returns the messages emitted for it
+    pub async fn send_message(&mut self, message: Message) -> Vec<Message> {
+        read_messages(&self.send_all(message.into_events()).await)
+    }
+    /// end the stream, returns the events emitted for it
+    pub async fn end(&mut self, error: Option<String>) -> Vec<Event> {
+        self.send(Event::StreamEnd(error)).await
+    }
+    /// wait for work queued through an [`OutputHandle`], returns the events it emitted
+    pub async fn scheduled(&mut self) -> Vec<Event> {
+        self.output.next_scheduled().await;
+        let events = self.output.take();
+        self.emitted.extend(events.iter().cloned());
+        events
+    }
+    /// a handle of the output the pipeline emits to
+    pub fn handle(&self) -> OutputHandle {
+        self.output.handle()
+    }
+    /// every event emitted since the harness was created
+    pub fn emitted(&self) -> &[Event] {
+        &self.emitted
+    }
+    /// the whole messages among [`Self::emitted`]
+    pub fn emitted_messages(&self) -> Vec<Message> {
+        read_messages(&self.emitted)
+    }
+    /// the context variables of the stream, set values here the way a script would
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
+    }
+    pub fn pipeline(&self) -> &P {
+        &self.pipeline
+    }
+    pub fn into_pipeline(self) -> P {
+        self.pipeline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use serde_json::json;
+
+    use super::*;
+    use crate::pipeline::ContextVar;
+
+    /// upper-cases the body and counts the messages
+    #[derive(Default)]
+    struct Upper {
+        messages: usize,
+    }
+    impl NativePipeline for Upper {
+        async fn process(&mut self, event: Event, _context: &mut Context, output: &mut Output) {
+            match event {
+                Event::Data(data) => {
+                    output.emit(Event::Data(Bytes::from(data.to_ascii_uppercase())))
+                }
+                Event::MessageEnd(tail) => {
+                    self.messages += 1;
+                    output.emit(Event::MessageEnd(tail));
+                }
+                event => output.emit(event),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_harness() {
+        let mut harness = PipelineHarness::new(Upper::default());
+        let head = json!({ "method": "GET" });
+        assert_eq!(
+            harness
+                .send_message(Message::new(head.clone(), "hello"))
+                .await,
+            vec![Message::new(head.clone(), "HELLO")]
+        );
+        assert_eq!(
+            harness.send(Event::data("bye")).await,
+            vec![Event::data("BYE")]
+        );
+        assert_eq!(harness.end(None).await, vec![Event::StreamEnd(None)]);
+        assert_eq!(harness.emitted().len(), 5);
+        assert_eq!(harness.emitted_messages().len(), 1);
+        assert_eq!(harness.pipeline().messages, 1);
+    }
+
+    /// tags each message head with the tenant of the stream and counts the messages in `served`
+    struct Tenant {
+        tenant: ContextVar<String>,
+        served: ContextVar<u32>,
+    }
+    impl NativePipeline for Tenant {
+        async fn process(&mut self, event: Event, context: &mut Context, output: &mut Output) {
+            match event {
+                Event::MessageStart(mut head) => {
+                    head["tenant"] = context.get(&self.tenant).into();
+                    let served = context.get(&self.served) + 1;
+                    context.set(&self.served, served).unwrap();
+                    output.emit(Event::MessageStart(head));
+                }
+                event => output.emit(event),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_harness_context() {
+        let mut harness = PipelineHarness::new(Tenant {
+            tenant: ContextVar::new("tenant", "public".to_string()),
+            served: ContextVar::new("served", 0),
+        });
+        let messages = harness.send_message(Message::new(json!({}), "")).await;
+        assert_eq!(messages[0].head["tenant"], "public");
+
+        // set by the routing script
+        harness.context_mut().set_value("tenant", json!("acme"));
+        let messages = harness.send_message(Message::new(json!({}), "")).await;
+        assert_eq!(messages[0].head["tenant"], "acme");
+        assert_eq!(harness.context().value("served"), Some(&json!(2)));
+    }
+
+    /// answers each request from another thread, the way a pipeline waits for async work
+    struct Deferred;
+    impl NativePipeline for Deferred {
+        async fn process(&mut self, event: Event, _context: &mut Context, output: &mut Output) {
+            if let Event::MessageEnd(_) = event {
+                let handle = output.handle();
+                std::thread::spawn(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    handle.emit_message(Message::new(json!({ "status": 200 }), "done"));
+                });
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_harness_scheduled() {
+        let mut harness = PipelineHarness::new(Deferred);
+        assert!(harness
+            .send_message(Message::new(json!({}), ""))
+            .await
+            .is_empty());
+        let events = harness.scheduled().await;
+        assert_eq!(
+            read_messages(&events),
+            vec![Message::new(json!({ "status": 200 }), "done")]
+        );
+
+        // work queued before an event runs with it
+        let handle = harness.handle();
+        assert!(handle.schedule(|output| output.emit(Event::data("queued"))));
+        assert_eq!(
+            harness.send(Event::data("input")).await,
+            vec![Event::data("queued")]
+        );
+        drop(harness);
+        assert!(!handle.emit(Event::data("gone")));
+    }
+}
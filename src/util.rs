@@ -8,9 +8,13 @@ pub fn init_logger(level: &str) {
         "error" => tracing::Level::ERROR,
         _ => tracing::Level::INFO,
     };
-    tracing::subscriber::set_global_default(
-        tracing_subscriber::fmt().with_max_level(level).finish(),
-    )
-    .unwrap();
+    // tests in the same binary share the global subscriber, the first one to set it wins
+    if tracing_subscriber::fmt()
+        .with_max_level(level)
+        .try_init()
+        .is_err()
+    {
+        return;
+    }
     tracing::info!("logger initialized with level: {}", level);
 }
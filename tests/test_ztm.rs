@@ -1,6 +1,5 @@
-use std::thread;
-
-use pipy_rs;
+#![cfg(feature = "libpipy")]
+use pipy_rs::api_client::SyncOptions;
 
 #[tokio::test]
 pub async fn start_ztm_agent() {
@@ -14,27 +13,51 @@ pub async fn start_ztm_agent() {
     let pipy = pipy_rs::PipyRepo::new(port);
     pipy.start();
 
-    let agent_files = vec!["api.js", "db.js", "main.js", "mesh.js", "options.js"];
     let agent_path = "tests/data/agent";
 
     let api_client = pipy_rs::api_client::ApiClient::new("127.0.0.1", port);
     let agent_name = "ztm_agent";
     api_client.create_codebase(agent_name).await.unwrap();
-    for file in agent_files {
-        let file_path = format!("{}/{}", agent_path, file);
-        let file_content = std::fs::read(file_path).unwrap();
-        api_client
-            .update_file(agent_name, file, file_content)
-            .await
-            .unwrap();
-    }
-    api_client.publish_changes(agent_name).await.unwrap();
+    let options = SyncOptions {
+        delete_missing: true,
+        main: Some("main.js".to_string()),
+        publish: true,
+    };
+    let report = api_client
+        .sync_dir(agent_name, agent_path, &options)
+        .await
+        .unwrap();
+    tracing::debug!("sync report: {:?}", report);
+    assert!(report.added.contains(&"api.js".to_string()));
+    assert!(report.published_version.is_some());
+
+    // nothing changes when syncing again
+    let report = api_client
+        .sync_dir(agent_name, agent_path, &options)
+        .await
+        .unwrap();
+    assert!(!report.is_changed(), "unexpected changes: {:?}", report);
     let _ = api_client.get_codebase(agent_name).await.unwrap();
-    api_client.start_repo(agent_name).await.unwrap();
+    let layout = api_client.get_codebase_layout(agent_name).await.unwrap();
+    assert!(!layout.modules.is_empty());
+    tracing::debug!("ztm agent layout:\n{}", layout.to_mermaid());
+    let db = tempfile::NamedTempFile::new().unwrap();
+    let db_path = db.path().to_str().unwrap();
+    let args = [
+        "--listen",
+        "127.0.0.1:7778",
+        "--database",
+        db_path,
+        "--reset",
+    ];
+    api_client.start_program(agent_name, &args).await.unwrap();
+    let program = api_client.current_program().await.unwrap().unwrap();
+    assert_eq!(program.codebase(), agent_name);
+    assert_eq!(program.args(), args);
     tracing::info!("start ztm agent");
 
-    // test curl localhost:7777
-    let resp = reqwest::get("http://127.0.0.1:7777/api/version")
+    // test curl localhost:7778, the port given by `--listen`
+    let resp = reqwest::get("http://127.0.0.1:7778/api/version")
         .await
         .unwrap();
     tracing::debug!("resp: {:?}", resp);